@@ -5,15 +5,118 @@ use super::errors::Result;
 use super::reply::*;
 use super::runtime::spawn;
 
+use std::collections::HashMap;
 use std::ffi::OsString;
 use std::fmt::Debug;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{
     future::Future,
     path::{Path, PathBuf},
 };
 
 use log::trace;
+use once_cell::sync::Lazy;
+
+// RequestContext describes the process which issues the request, and what
+// the request operates on
+#[derive(Debug, Clone, Copy)]
+pub struct RequestContext {
+    pub unique: u64,
+    // the process which issues the request. FUSE reports the thread, so it's
+    // resolved to its thread group
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+
+    // the file handle which the request operates on, if any
+    pub fh: Option<u64>,
+    // the flags which the file is opened with, or is being opened with
+    pub flags: Option<i32>,
+    // the range of a READ or WRITE, or the buffer size of a GETXATTR
+    pub offset: Option<i64>,
+    pub size: Option<usize>,
+}
+
+impl From<&Request<'_>> for RequestContext {
+    fn from(req: &Request<'_>) -> Self {
+        Self {
+            unique: req.unique(),
+            pid: tgid(req.pid()),
+            uid: req.uid(),
+            gid: req.gid(),
+            fh: None,
+            flags: None,
+            offset: None,
+            size: None,
+        }
+    }
+}
+
+// the thread group of a thread is cached for a short while, as a thread
+// usually issues several requests together, and a tid can be reused after the
+// thread exits
+const TGID_TTL: Duration = Duration::from_secs(1);
+
+static TGIDS: Lazy<Mutex<HashMap<u32, (Instant, u32)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// tgid returns the thread group of the thread, or the thread itself if it has
+// exited
+fn tgid(tid: u32) -> u32 {
+    let now = Instant::now();
+    if let Some((time, tgid)) = TGIDS.lock().unwrap().get(&tid) {
+        if now.duration_since(*time) < TGID_TTL {
+            return *tgid;
+        }
+    }
+
+    let tgid = std::fs::read_to_string(format!("/proc/{}/status", tid))
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("Tgid:"))
+                .and_then(|tgid| tgid.trim().parse().ok())
+        })
+        .unwrap_or(tid);
+    trace!("thread {} belongs to {}", tid, tgid);
+
+    let mut tgids = TGIDS.lock().unwrap();
+    tgids.retain(|_, (time, _)| now.duration_since(*time) < TGID_TTL);
+    tgids.insert(tid, (now, tgid));
+    tgid
+}
+
+impl RequestContext {
+    pub fn with_fh(self, fh: u64) -> Self {
+        Self {
+            fh: Some(fh),
+            ..self
+        }
+    }
+
+    pub fn with_flags(self, flags: i32) -> Self {
+        Self {
+            flags: Some(flags),
+            ..self
+        }
+    }
+
+    pub fn with_range(self, offset: i64, size: usize) -> Self {
+        Self {
+            offset: Some(offset),
+            size: Some(size),
+            ..self
+        }
+    }
+
+    pub fn with_size(self, size: usize) -> Self {
+        Self {
+            size: Some(size),
+            ..self
+        }
+    }
+}
 
 pub fn spawn_reply<F, R, V>(id: u64, reply: R, f: F)
 where
@@ -34,14 +137,15 @@ pub trait AsyncFileSystemImpl: Send + Sync {
 
     fn destroy(&self);
 
-    async fn lookup(&self, parent: u64, name: OsString) -> Result<Entry>;
+    async fn lookup(&self, ctx: RequestContext, parent: u64, name: OsString) -> Result<Entry>;
 
-    async fn forget(&self, ino: u64, nlookup: u64);
+    async fn forget(&self, ctx: RequestContext, ino: u64, nlookup: u64);
 
-    async fn getattr(&self, ino: u64) -> Result<Attr>;
+    async fn getattr(&self, ctx: RequestContext, ino: u64) -> Result<Attr>;
 
     async fn setattr(
         &self,
+        ctx: RequestContext,
         ino: u64,
         mode: Option<u32>,
         uid: Option<u32>,
@@ -57,10 +161,11 @@ pub trait AsyncFileSystemImpl: Send + Sync {
         flags: Option<u32>,
     ) -> Result<Attr>;
 
-    async fn readlink(&self, ino: u64) -> Result<Data>;
+    async fn readlink(&self, ctx: RequestContext, ino: u64) -> Result<Data>;
 
     async fn mknod(
         &self,
+        ctx: RequestContext,
         parent: u64,
         name: OsString,
         mode: u32,
@@ -68,16 +173,30 @@ pub trait AsyncFileSystemImpl: Send + Sync {
         rdev: u32,
     ) -> Result<Entry>;
 
-    async fn mkdir(&self, parent: u64, name: OsString, umask: u32, mode: u32) -> Result<Entry>;
+    async fn mkdir(
+        &self,
+        ctx: RequestContext,
+        parent: u64,
+        name: OsString,
+        umask: u32,
+        mode: u32,
+    ) -> Result<Entry>;
 
-    async fn unlink(&self, parent: u64, name: OsString) -> Result<()>;
+    async fn unlink(&self, ctx: RequestContext, parent: u64, name: OsString) -> Result<()>;
 
-    async fn rmdir(&self, parent: u64, name: OsString) -> Result<()>;
+    async fn rmdir(&self, ctx: RequestContext, parent: u64, name: OsString) -> Result<()>;
 
-    async fn symlink(&self, parent: u64, name: OsString, link: PathBuf) -> Result<Entry>;
+    async fn symlink(
+        &self,
+        ctx: RequestContext,
+        parent: u64,
+        name: OsString,
+        link: PathBuf,
+    ) -> Result<Entry>;
 
     async fn rename(
         &self,
+        ctx: RequestContext,
         parent: u64,
         name: OsString,
         newparent: u64,
@@ -85,12 +204,19 @@ pub trait AsyncFileSystemImpl: Send + Sync {
         flags: u32,
     ) -> Result<()>;
 
-    async fn link(&self, ino: u64, newparent: u64, newname: OsString) -> Result<Entry>;
+    async fn link(
+        &self,
+        ctx: RequestContext,
+        ino: u64,
+        newparent: u64,
+        newname: OsString,
+    ) -> Result<Entry>;
 
-    async fn open(&self, ino: u64, flags: i32) -> Result<Open>;
+    async fn open(&self, ctx: RequestContext, ino: u64, flags: i32) -> Result<Open>;
 
     async fn read(
         &self,
+        ctx: RequestContext,
         ino: u64,
         fh: u64,
         offset: i64,
@@ -101,6 +227,7 @@ pub trait AsyncFileSystemImpl: Send + Sync {
 
     async fn write(
         &self,
+        ctx: RequestContext,
         ino: u64,
         fh: u64,
         offset: i64,
@@ -110,10 +237,11 @@ pub trait AsyncFileSystemImpl: Send + Sync {
         lock_owner: Option<u64>,
     ) -> Result<Write>;
 
-    async fn flush(&self, ino: u64, fh: u64, lock_owner: u64) -> Result<()>;
+    async fn flush(&self, ctx: RequestContext, ino: u64, fh: u64, lock_owner: u64) -> Result<()>;
 
     async fn release(
         &self,
+        ctx: RequestContext,
         ino: u64,
         fh: u64,
         flags: i32,
@@ -121,20 +249,28 @@ pub trait AsyncFileSystemImpl: Send + Sync {
         flush: bool,
     ) -> Result<()>;
 
-    async fn fsync(&self, ino: u64, fh: u64, datasync: bool) -> Result<()>;
+    async fn fsync(&self, ctx: RequestContext, ino: u64, fh: u64, datasync: bool) -> Result<()>;
 
-    async fn opendir(&self, ino: u64, flags: i32) -> Result<Open>;
+    async fn opendir(&self, ctx: RequestContext, ino: u64, flags: i32) -> Result<Open>;
 
-    async fn readdir(&self, ino: u64, fh: u64, offset: i64, reply: ReplyDirectory);
+    async fn readdir(
+        &self,
+        ctx: RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        reply: ReplyDirectory,
+    );
 
-    async fn releasedir(&self, ino: u64, fh: u64, flags: i32) -> Result<()>;
+    async fn releasedir(&self, ctx: RequestContext, ino: u64, fh: u64, flags: i32) -> Result<()>;
 
-    async fn fsyncdir(&self, ino: u64, fh: u64, datasync: bool) -> Result<()>;
+    async fn fsyncdir(&self, ctx: RequestContext, ino: u64, fh: u64, datasync: bool) -> Result<()>;
 
-    async fn statfs(&self, ino: u64) -> Result<StatFs>;
+    async fn statfs(&self, ctx: RequestContext, ino: u64) -> Result<StatFs>;
 
     async fn setxattr(
         &self,
+        ctx: RequestContext,
         ino: u64,
         name: OsString,
         value: Vec<u8>,
@@ -142,16 +278,23 @@ pub trait AsyncFileSystemImpl: Send + Sync {
         position: u32,
     ) -> Result<()>;
 
-    async fn getxattr(&self, ino: u64, name: OsString, size: u32) -> Result<Xattr>;
+    async fn getxattr(
+        &self,
+        ctx: RequestContext,
+        ino: u64,
+        name: OsString,
+        size: u32,
+    ) -> Result<Xattr>;
 
-    async fn listxattr(&self, ino: u64, size: u32) -> Result<Xattr>;
+    async fn listxattr(&self, ctx: RequestContext, ino: u64, size: u32) -> Result<Xattr>;
 
-    async fn removexattr(&self, ino: u64, name: OsString) -> Result<()>;
+    async fn removexattr(&self, ctx: RequestContext, ino: u64, name: OsString) -> Result<()>;
 
-    async fn access(&self, ino: u64, mask: i32) -> Result<()>;
+    async fn access(&self, ctx: RequestContext, ino: u64, mask: i32) -> Result<()>;
 
     async fn create(
         &self,
+        ctx: RequestContext,
         parent: u64,
         name: OsString,
         mode: u32,
@@ -163,6 +306,7 @@ pub trait AsyncFileSystemImpl: Send + Sync {
 
     async fn getlk(
         &self,
+        ctx: RequestContext,
         ino: u64,
         fh: u64,
         lock_owner: u64,
@@ -174,6 +318,7 @@ pub trait AsyncFileSystemImpl: Send + Sync {
 
     async fn setlk(
         &self,
+        ctx: RequestContext,
         ino: u64,
         fh: u64,
         lock_owner: u64,
@@ -184,7 +329,7 @@ pub trait AsyncFileSystemImpl: Send + Sync {
         sleep: bool,
     ) -> Result<()>;
 
-    async fn bmap(&self, ino: u64, blocksize: u32, idx: u64, reply: ReplyBmap);
+    async fn bmap(&self, ctx: RequestContext, ino: u64, blocksize: u32, idx: u64, reply: ReplyBmap);
 }
 
 pub struct AsyncFileSystem<T>(Arc<T>);
@@ -215,29 +360,30 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
     }
 
     fn lookup(&mut self, req: &Request, parent: u64, name: &std::ffi::OsStr, reply: ReplyEntry) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         let name = name.to_owned();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.lookup(parent, name).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.lookup(ctx, parent, name).await
         });
     }
 
-    fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
+    fn forget(&mut self, req: &Request, ino: u64, nlookup: u64) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
 
         // TODO: union the spawn function for request without reply
         spawn(async move {
-            async_impl.forget(ino, nlookup).await;
+            async_impl.forget(ctx, ino, nlookup).await;
         });
     }
 
     fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
-        spawn_reply(
-            req.unique(),
-            reply,
-            async move { async_impl.getattr(ino).await },
-        );
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.getattr(ctx, ino).await
+        });
     }
 
     fn setattr(
@@ -258,21 +404,23 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         flags: Option<u32>,
         reply: ReplyAttr,
     ) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
+        spawn_reply(ctx.unique, reply, async move {
             async_impl
                 .setattr(
-                    ino, mode, uid, gid, size, atime, mtime, ctime, fh, crtime, chgtime, bkuptime,
-                    flags,
+                    ctx, ino, mode, uid, gid, size, atime, mtime, ctime, fh, crtime, chgtime,
+                    bkuptime, flags,
                 )
                 .await
         });
     }
 
     fn readlink(&mut self, req: &Request, ino: u64, reply: ReplyData) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.readlink(ino).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.readlink(ctx, ino).await
         });
     }
     fn mknod(
@@ -285,10 +433,11 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         rdev: u32,
         reply: ReplyEntry,
     ) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         let name = name.to_owned();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.mknod(parent, name, mode, umask, rdev).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.mknod(ctx, parent, name, mode, umask, rdev).await
         });
     }
     fn mkdir(
@@ -300,24 +449,27 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         umask: u32,
         reply: ReplyEntry,
     ) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         let name = name.to_owned();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.mkdir(parent, name, umask, mode).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.mkdir(ctx, parent, name, umask, mode).await
         });
     }
     fn unlink(&mut self, req: &Request, parent: u64, name: &std::ffi::OsStr, reply: ReplyEmpty) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         let name = name.to_owned();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.unlink(parent, name).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.unlink(ctx, parent, name).await
         });
     }
     fn rmdir(&mut self, req: &Request, parent: u64, name: &std::ffi::OsStr, reply: ReplyEmpty) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         let name = name.to_owned();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.rmdir(parent, name).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.rmdir(ctx, parent, name).await
         });
     }
     fn symlink(
@@ -328,11 +480,12 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         link: &Path,
         reply: ReplyEntry,
     ) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         let name = name.to_owned();
         let link = link.to_owned();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.symlink(parent, name, link).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.symlink(ctx, parent, name, link).await
         });
     }
     fn rename(
@@ -345,12 +498,13 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         flags: u32,
         reply: ReplyEmpty,
     ) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         let name = name.to_owned();
         let newname = newname.to_owned();
-        spawn_reply(req.unique(), reply, async move {
+        spawn_reply(ctx.unique, reply, async move {
             async_impl
-                .rename(parent, name, newparent, newname, flags)
+                .rename(ctx, parent, name, newparent, newname, flags)
                 .await
         });
     }
@@ -362,16 +516,18 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         newname: &std::ffi::OsStr,
         reply: ReplyEntry,
     ) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         let newname = newname.to_owned();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.link(ino, newparent, newname).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.link(ctx, ino, newparent, newname).await
         });
     }
     fn open(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.open(ino, flags).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.open(ctx, ino, flags).await
         });
     }
     fn read(
@@ -385,10 +541,13 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         lock_owner: Option<u64>,
         reply: ReplyData,
     ) {
+        let ctx = RequestContext::from(req)
+            .with_fh(fh)
+            .with_range(offset, size as usize);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
+        spawn_reply(ctx.unique, reply, async move {
             async_impl
-                .read(ino, fh, offset, size, flags, lock_owner)
+                .read(ctx, ino, fh, offset, size, flags, lock_owner)
                 .await
         });
     }
@@ -404,18 +563,22 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         lock_owner: Option<u64>,
         reply: ReplyWrite,
     ) {
+        let ctx = RequestContext::from(req)
+            .with_fh(fh)
+            .with_range(offset, data.len());
         let async_impl = self.0.clone();
         let data = data.to_owned();
-        spawn_reply(req.unique(), reply, async move {
+        spawn_reply(ctx.unique, reply, async move {
             async_impl
-                .write(ino, fh, offset, data, write_flags, flags, lock_owner)
+                .write(ctx, ino, fh, offset, data, write_flags, flags, lock_owner)
                 .await
         });
     }
     fn flush(&mut self, req: &Request, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty) {
+        let ctx = RequestContext::from(req).with_fh(fh);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.flush(ino, fh, lock_owner).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.flush(ctx, ino, fh, lock_owner).await
         });
     }
     fn release(
@@ -428,48 +591,55 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         flush: bool,
         reply: ReplyEmpty,
     ) {
+        let ctx = RequestContext::from(req).with_fh(fh);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.release(ino, fh, flags, lock_owner, flush).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl
+                .release(ctx, ino, fh, flags, lock_owner, flush)
+                .await
         });
     }
     fn fsync(&mut self, req: &Request, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        let ctx = RequestContext::from(req).with_fh(fh);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.fsync(ino, fh, datasync).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.fsync(ctx, ino, fh, datasync).await
         });
     }
     fn opendir(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.opendir(ino, flags).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.opendir(ctx, ino, flags).await
         });
     }
-    fn readdir(&mut self, _req: &Request, ino: u64, fh: u64, offset: i64, reply: ReplyDirectory) {
+    fn readdir(&mut self, req: &Request, ino: u64, fh: u64, offset: i64, reply: ReplyDirectory) {
+        let ctx = RequestContext::from(req).with_fh(fh);
         let async_impl = self.0.clone();
         spawn(async move {
-            async_impl.readdir(ino, fh, offset, reply).await;
+            async_impl.readdir(ctx, ino, fh, offset, reply).await;
         });
     }
     fn releasedir(&mut self, req: &Request, ino: u64, fh: u64, flags: i32, reply: ReplyEmpty) {
+        let ctx = RequestContext::from(req).with_fh(fh);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.releasedir(ino, fh, flags).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.releasedir(ctx, ino, fh, flags).await
         });
     }
     fn fsyncdir(&mut self, req: &Request, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        let ctx = RequestContext::from(req).with_fh(fh);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.fsyncdir(ino, fh, datasync).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.fsyncdir(ctx, ino, fh, datasync).await
         });
     }
     fn statfs(&mut self, req: &Request, ino: u64, reply: ReplyStatfs) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
-        spawn_reply(
-            req.unique(),
-            reply,
-            async move { async_impl.statfs(ino).await },
-        );
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.statfs(ctx, ino).await
+        });
     }
     fn setxattr(
         &mut self,
@@ -481,11 +651,14 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         position: u32,
         reply: ReplyEmpty,
     ) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         let name = name.to_owned();
         let value = value.to_owned();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.setxattr(ino, name, value, flags, position).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl
+                .setxattr(ctx, ino, name, value, flags, position)
+                .await
         });
     }
     fn getxattr(
@@ -496,29 +669,33 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         size: u32,
         reply: ReplyXattr,
     ) {
+        let ctx = RequestContext::from(req).with_size(size as usize);
         let async_impl = self.0.clone();
         let name = name.to_owned();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.getxattr(ino, name, size).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.getxattr(ctx, ino, name, size).await
         });
     }
     fn listxattr(&mut self, req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.listxattr(ino, size).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.listxattr(ctx, ino, size).await
         });
     }
     fn removexattr(&mut self, req: &Request, ino: u64, name: &std::ffi::OsStr, reply: ReplyEmpty) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         let name = name.to_owned();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.removexattr(ino, name).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.removexattr(ctx, ino, name).await
         });
     }
     fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
-            async_impl.access(ino, mask).await
+        spawn_reply(ctx.unique, reply, async move {
+            async_impl.access(ctx, ino, mask).await
         });
     }
     fn create(
@@ -534,11 +711,12 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         let uid = req.uid();
         let gid = req.gid();
 
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         let name = name.to_owned();
-        spawn_reply(req.unique(), reply, async move {
+        spawn_reply(ctx.unique, reply, async move {
             async_impl
-                .create(parent, name, mode, umask, flags, uid, gid)
+                .create(ctx, parent, name, mode, umask, flags, uid, gid)
                 .await
         });
     }
@@ -554,10 +732,11 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         pid: u32,
         reply: ReplyLock,
     ) {
+        let ctx = RequestContext::from(req).with_fh(fh);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
+        spawn_reply(ctx.unique, reply, async move {
             async_impl
-                .getlk(ino, fh, lock_owner, start, end, typ, pid)
+                .getlk(ctx, ino, fh, lock_owner, start, end, typ, pid)
                 .await
         });
     }
@@ -574,17 +753,19 @@ impl<T: AsyncFileSystemImpl + 'static> Filesystem for AsyncFileSystem<T> {
         sleep: bool,
         reply: ReplyEmpty,
     ) {
+        let ctx = RequestContext::from(req).with_fh(fh);
         let async_impl = self.0.clone();
-        spawn_reply(req.unique(), reply, async move {
+        spawn_reply(ctx.unique, reply, async move {
             async_impl
-                .setlk(ino, fh, lock_owner, start, end, typ, pid, sleep)
+                .setlk(ctx, ino, fh, lock_owner, start, end, typ, pid, sleep)
                 .await
         });
     }
-    fn bmap(&mut self, _req: &Request, ino: u64, blocksize: u32, idx: u64, reply: ReplyBmap) {
+    fn bmap(&mut self, req: &Request, ino: u64, blocksize: u32, idx: u64, reply: ReplyBmap) {
+        let ctx = RequestContext::from(req);
         let async_impl = self.0.clone();
         spawn(async move {
-            async_impl.bmap(ino, blocksize, idx, reply).await;
+            async_impl.bmap(ctx, ino, blocksize, idx, reply).await;
         });
     }
 }
@@ -0,0 +1,464 @@
+use super::errors::{HookFsError as Error, Result};
+use super::runtime::spawn_blocking;
+
+use log::{debug, error, trace};
+
+use libc::{lgetxattr, lremovexattr, lsetxattr};
+use nix::errno::Errno;
+use nix::sys::stat::{utimensat, UtimensatFlags};
+use nix::sys::time::TimeSpec;
+use nix::unistd::{chown, fchownat, FchownatFlags, Gid, Uid};
+use rand::Rng;
+
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{OpenOptions, Permissions};
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+// the original content larger than this is spilled into the holding
+// directory, instead of being kept in memory
+const SPILL_SIZE: u64 = 1 << 20;
+
+// Data is the original content of a range
+#[derive(Debug)]
+pub enum Data {
+    Memory(Vec<u8>),
+    Spilled(PathBuf),
+}
+
+// Entry records how to undo a change which has not been synced
+#[derive(Debug)]
+pub enum Entry {
+    Write {
+        path: PathBuf,
+        offset: u64,
+        data: Data,
+        size: u64,
+    },
+    Create {
+        path: PathBuf,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    // the unlinked file is held by a hard link in the holding directory
+    Unlink {
+        path: PathBuf,
+        held: PathBuf,
+    },
+    Rmdir {
+        path: PathBuf,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    },
+    // the original permission, owner and times of a file
+    Attr {
+        path: PathBuf,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+        atime: (i64, i64),
+        mtime: (i64, i64),
+    },
+    // the original value of an extended attribute, or None if it was absent
+    Xattr {
+        path: PathBuf,
+        name: OsString,
+        value: Option<Vec<u8>>,
+    },
+}
+
+impl Entry {
+    // range saves the original content of [offset, offset + len) and the
+    // original size of the file. A large content is copied to the spill path.
+    // It returns None if the file doesn't exist, e.g. it's a phantom.
+    pub async fn range(
+        path: &Path,
+        offset: u64,
+        len: u64,
+        spill: PathBuf,
+    ) -> Result<Option<Entry>> {
+        let path = path.to_owned();
+        spawn_blocking(move || -> Result<Option<Entry>> {
+            let mut file = match std::fs::File::open(&path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            let size = file.metadata()?.len();
+            let len = len.min(size.saturating_sub(offset));
+            file.seek(SeekFrom::Start(offset))?;
+
+            let data = if len > SPILL_SIZE {
+                trace!(
+                    "spill {} bytes of {} to {}",
+                    len,
+                    path.display(),
+                    spill.display()
+                );
+                if let Some(holding) = spill.parent() {
+                    std::fs::create_dir_all(holding)?;
+                }
+                let mut spilled = std::fs::File::create(&spill)?;
+                let data = Data::Spilled(spill);
+                std::io::copy(&mut file.take(len), &mut spilled)?;
+                data
+            } else {
+                let mut data = Vec::new();
+                file.take(len).read_to_end(&mut data)?;
+                Data::Memory(data)
+            };
+
+            Ok(Some(Entry::Write {
+                path,
+                offset,
+                data,
+                size,
+            }))
+        })
+        .await?
+    }
+
+    // unlink holds the file by a hard link, before it's unlinked. It returns
+    // None if the file cannot be held, e.g. it's on another filesystem.
+    pub async fn unlink(path: &Path, held: PathBuf) -> Result<Option<Entry>> {
+        let path = path.to_owned();
+        spawn_blocking(move || -> Result<Option<Entry>> {
+            if let Some(holding) = held.parent() {
+                std::fs::create_dir_all(holding)?;
+            }
+            if let Err(err) = std::fs::hard_link(&path, &held) {
+                debug!("fail to hold {}: {}", path.display(), err);
+                return Ok(None);
+            }
+
+            Ok(Some(Entry::Unlink { path, held }))
+        })
+        .await?
+    }
+
+    // rmdir saves the permission and owner of the directory
+    pub async fn rmdir(path: &Path) -> Result<Entry> {
+        let path = path.to_owned();
+        spawn_blocking(move || -> Result<Entry> {
+            let metadata = std::fs::symlink_metadata(&path)?;
+
+            Ok(Entry::Rmdir {
+                path,
+                mode: metadata.mode(),
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+            })
+        })
+        .await?
+    }
+
+    // attr saves the permission, owner and times of the file, before they are
+    // changed by a setattr
+    pub async fn attr(path: &Path) -> Result<Entry> {
+        let path = path.to_owned();
+        spawn_blocking(move || -> Result<Entry> {
+            let metadata = std::fs::symlink_metadata(&path)?;
+
+            Ok(Entry::Attr {
+                path,
+                mode: metadata.mode(),
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+                atime: (metadata.atime(), metadata.atime_nsec()),
+                mtime: (metadata.mtime(), metadata.mtime_nsec()),
+            })
+        })
+        .await?
+    }
+
+    // xattr saves the original value of the extended attribute
+    pub async fn xattr(path: &Path, name: &OsStr) -> Result<Entry> {
+        let path = path.to_owned();
+        let name = name.to_owned();
+        spawn_blocking(move || -> Result<Entry> {
+            let cpath = CString::new(path.as_os_str().as_bytes())?;
+            let cname = CString::new(name.as_bytes())?;
+            let get = |value: &mut [u8]| {
+                let ret = unsafe {
+                    lgetxattr(
+                        cpath.as_ptr(),
+                        cname.as_ptr(),
+                        value.as_mut_ptr() as *mut libc::c_void,
+                        value.len(),
+                    )
+                };
+                match ret {
+                    -1 => Err(Errno::last()),
+                    len => Ok(len as usize),
+                }
+            };
+
+            // the value may change between the probe and the fetch
+            let value = loop {
+                let len = match get(&mut []) {
+                    Ok(len) => len,
+                    Err(Errno::ENODATA) => break None,
+                    Err(errno) => return Err(Error::Sys(errno)),
+                };
+                let mut value = vec![0u8; len];
+                match get(&mut value) {
+                    Ok(len) => {
+                        value.truncate(len);
+                        break Some(value);
+                    }
+                    Err(Errno::ERANGE) => continue,
+                    Err(Errno::ENODATA) => break None,
+                    Err(errno) => return Err(Error::Sys(errno)),
+                }
+            };
+
+            Ok(Entry::Xattr { path, name, value })
+        })
+        .await?
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Entry::Write { path, .. } => path,
+            Entry::Create { path } => path,
+            Entry::Rename { to, .. } => to,
+            Entry::Unlink { path, .. } => path,
+            Entry::Rmdir { path, .. } => path,
+            Entry::Attr { path, .. } => path,
+            Entry::Xattr { path, .. } => path,
+        }
+    }
+
+    // is_data tells whether the change is synced by the fsync of the file,
+    // instead of the fsync of its directory
+    fn is_data(&self) -> bool {
+        matches!(
+            self,
+            Entry::Write { .. } | Entry::Attr { .. } | Entry::Xattr { .. }
+        )
+    }
+
+    fn undo(&self) -> std::io::Result<()> {
+        trace!("undo {:?}", self);
+        match self {
+            Entry::Write {
+                path,
+                offset,
+                data,
+                size,
+            } => {
+                let mut file = OpenOptions::new().write(true).open(path)?;
+                match data {
+                    Data::Memory(data) => file.write_all_at(data, *offset)?,
+                    Data::Spilled(spill) => {
+                        file.seek(SeekFrom::Start(*offset))?;
+                        std::io::copy(&mut std::fs::File::open(spill)?, &mut file)?;
+                    }
+                }
+                file.set_len(*size)
+            }
+            Entry::Create { path } => {
+                if std::fs::symlink_metadata(path)?.is_dir() {
+                    std::fs::remove_dir(path)
+                } else {
+                    std::fs::remove_file(path)
+                }
+            }
+            Entry::Rename { from, to } => std::fs::rename(to, from),
+            Entry::Unlink { path, held } => std::fs::hard_link(held, path),
+            Entry::Rmdir {
+                path,
+                mode,
+                uid,
+                gid,
+            } => {
+                std::fs::create_dir(path)?;
+                std::fs::set_permissions(path, Permissions::from_mode(*mode))?;
+                chown(path, Some(Uid::from_raw(*uid)), Some(Gid::from_raw(*gid)))
+                    .map_err(|_| std::io::Error::last_os_error())
+            }
+            Entry::Attr {
+                path,
+                mode,
+                uid,
+                gid,
+                atime,
+                mtime,
+            } => {
+                // the permission of a symlink cannot be changed
+                if !std::fs::symlink_metadata(path)?.file_type().is_symlink() {
+                    std::fs::set_permissions(path, Permissions::from_mode(*mode))?;
+                }
+                fchownat(
+                    None,
+                    path,
+                    Some(Uid::from_raw(*uid)),
+                    Some(Gid::from_raw(*gid)),
+                    FchownatFlags::NoFollowSymlink,
+                )
+                .map_err(|_| std::io::Error::last_os_error())?;
+
+                let time = |(sec, nsec): (i64, i64)| {
+                    TimeSpec::from(libc::timespec {
+                        tv_sec: sec,
+                        tv_nsec: nsec,
+                    })
+                };
+                utimensat(
+                    None,
+                    path,
+                    &time(*atime),
+                    &time(*mtime),
+                    UtimensatFlags::NoFollowSymlink,
+                )
+                .map_err(|_| std::io::Error::last_os_error())
+            }
+            Entry::Xattr { path, name, value } => {
+                let cpath = CString::new(path.as_os_str().as_bytes())?;
+                let cname = CString::new(name.as_bytes())?;
+                let ret = match value {
+                    Some(value) => unsafe {
+                        lsetxattr(
+                            cpath.as_ptr(),
+                            cname.as_ptr(),
+                            value.as_ptr() as *const libc::c_void,
+                            value.len(),
+                            0,
+                        )
+                    },
+                    None => unsafe { lremovexattr(cpath.as_ptr(), cname.as_ptr()) },
+                };
+                match ret {
+                    -1 => Err(std::io::Error::last_os_error()),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+// the held files and the spilled content are removed once the entry is
+// synced or rolled back
+impl Drop for Entry {
+    fn drop(&mut self) {
+        let held = match self {
+            Entry::Write {
+                data: Data::Spilled(spill),
+                ..
+            } => spill,
+            Entry::Unlink { held, .. } => held,
+            _ => return,
+        };
+        if let Err(err) = std::fs::remove_file(&held) {
+            debug!("fail to remove {}: {}", held.display(), err);
+        }
+    }
+}
+
+// PowerLoss describes which of the unsynced changes are lost
+#[derive(Debug, Clone, Copy)]
+pub struct PowerLoss {
+    // the probability that a change is lost
+    pub probability: f64,
+    // the changes reach the disk in any order, so every change is lost
+    // independently. Otherwise they reach the disk in order, and all changes
+    // after the first lost one are lost as well
+    pub reorder: bool,
+}
+
+// Journal keeps the changes since the last fsync or fsyncdir, so that they
+// can be discarded to simulate a power loss
+#[derive(Debug)]
+pub struct Journal {
+    entries: Vec<Entry>,
+
+    // the unlinked files and the spilled content are kept in the holding
+    // directory, which is created on demand and hidden from the mount
+    holding: PathBuf,
+    held: u64,
+}
+
+impl Journal {
+    pub fn new(holding: PathBuf) -> Journal {
+        Journal {
+            entries: Vec::new(),
+            holding,
+            held: 0,
+        }
+    }
+
+    pub fn holding(&self) -> &Path {
+        &self.holding
+    }
+
+    // hold returns an unused path in the holding directory
+    pub fn hold(&mut self) -> PathBuf {
+        self.held += 1;
+        self.holding.join(self.held.to_string())
+    }
+    pub fn push(&mut self, entry: Entry) {
+        trace!("journal {:?}", entry);
+        self.entries.push(entry);
+    }
+
+    // sync forgets the data and attribute changes of the file
+    pub fn sync(&mut self, path: &Path) {
+        self.entries
+            .retain(|entry| !entry.is_data() || entry.path() != path);
+    }
+
+    // sync_dir forgets the entry changes inside the directory
+    pub fn sync_dir(&mut self, dir: &Path) {
+        self.entries
+            .retain(|entry| entry.is_data() || entry.path().parent() != Some(dir));
+    }
+
+    // rollback undoes the lost changes in the reverse order, and forgets the
+    // others as they have reached the disk
+    pub fn rollback(&mut self, loss: PowerLoss) {
+        let mut rng = rand::thread_rng();
+        let mut cut = false;
+        let lost: Vec<_> = self
+            .entries
+            .iter()
+            .map(|_| {
+                let p: f64 = rng.gen();
+                if loss.reorder {
+                    p < loss.probability
+                } else {
+                    cut = cut || p < loss.probability;
+                    cut
+                }
+            })
+            .collect();
+        debug!(
+            "rollback {} of {} unsynced changes",
+            lost.iter().filter(|lost| **lost).count(),
+            self.entries.len()
+        );
+
+        for (entry, lost) in self.entries.drain(..).zip(lost).rev() {
+            if !lost {
+                continue;
+            }
+            if let Err(err) = entry.undo() {
+                error!("fail to undo {:?}: {}", entry, err);
+            }
+        }
+    }
+}
+
+// the changes which are never synced are kept, as if the power is still on
+impl Drop for Journal {
+    fn drop(&mut self) {
+        self.entries.clear();
+        if let Err(err) = std::fs::remove_dir(&self.holding) {
+            debug!("fail to remove {}: {}", self.holding.display(), err);
+        }
+    }
+}
@@ -1,7 +1,9 @@
 mod async_fs;
 mod errors;
+mod journal;
 mod reply;
 pub mod runtime;
+mod snapshot;
 
 use crate::injector::Injector;
 use crate::injector::Method;
@@ -20,13 +22,13 @@ use nix::fcntl::{open, readlink, renameat, OFlag};
 use nix::sys::stat;
 use nix::sys::statfs;
 use nix::sys::time::{TimeVal, TimeValLike};
+use nix::sys::uio::{pread, pwrite};
 use nix::unistd::{
     chown, fchown, fsync, linkat, mkdir, symlinkat, truncate, unlink, AccessFlags, Gid,
     LinkatFlags, Uid,
 };
 
 use tokio::fs;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use log::{debug, error, trace};
 
@@ -37,42 +39,115 @@ use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 
-pub use async_fs::{AsyncFileSystem, AsyncFileSystemImpl};
+pub use async_fs::{AsyncFileSystem, AsyncFileSystemImpl, RequestContext};
 pub use errors::{HookFsError as Error, Result};
-pub use reply::Reply;
+pub use journal::PowerLoss;
+use journal::{Entry as JournalEntry, Journal};
 use reply::*;
+pub use reply::{DirEntries, DirEntry, Rename, Reply, WriteData, Xattr};
 use runtime::spawn_blocking;
+use snapshot::Snapshots;
 
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, RwLockWriteGuard};
+use tokio::time::delay_for;
+
+// the journal holds the unlinked files and the large original content in this
+// directory under the original path, which is hidden from the mount
+const HOLDING_DIR: &str = ".toda-journal";
 
 // use fuse::consts::FOPEN_DIRECT_IO;
 
+// inject returns the guard which should be held until the operation completes
 macro_rules! inject {
-    ($self:ident, $method:ident, $path:expr) => {
+    ($self:ident, $ctx:expr, $method:ident, $path:expr) => {
+        if $self.enable_injection.load(Ordering::SeqCst) {
+            let path = $self.rebuild_path($path)?;
+            $self
+                .injector
+                .inject(&$ctx, &Method::$method, path.as_path())
+                .await?;
+            $self
+                .injector
+                .admit(&$ctx, &Method::$method, path.as_path())
+                .await?
+        } else {
+            None
+        }
+    };
+}
+
+macro_rules! inject_post {
+    ($self:ident, $ctx:expr, $method:ident, $path:expr) => {
         if $self.enable_injection.load(Ordering::SeqCst) {
             $self
                 .injector
-                .inject(&Method::$method, $self.rebuild_path($path)?.as_path())
+                .inject_post(
+                    &$ctx,
+                    &Method::$method,
+                    $self.rebuild_path($path)?.as_path(),
+                )
+                .await?;
+        }
+    };
+}
+
+macro_rules! inject_io {
+    ($self:ident, $ctx:expr, $method:ident, $path:expr, $offset:expr, $size:expr) => {
+        if $self.enable_injection.load(Ordering::SeqCst) {
+            $self
+                .injector
+                .inject_io(
+                    &$ctx,
+                    &Method::$method,
+                    $self.rebuild_path($path)?.as_path(),
+                    $offset,
+                    $size,
+                )
                 .await?;
         }
     };
 }
 
 macro_rules! inject_attr {
-    ($self:ident, $attr:ident, $path:expr) => {
+    ($self:ident, $ctx:expr, $attr:ident, $path:expr) => {
         if $self.enable_injection.load(Ordering::SeqCst) {
             $self
                 .injector
-                .inject_attr(&mut $attr, $self.rebuild_path($path)?.as_path());
+                .inject_attr(&$ctx, &mut $attr, $self.rebuild_path($path)?.as_path());
+        }
+    };
+}
+
+macro_rules! inject_write {
+    ($self:ident, $ctx:expr, $write:ident, $path:expr) => {
+        if $self.enable_injection.load(Ordering::SeqCst) {
+            $self
+                .injector
+                .inject_write(&$ctx, &mut $write, $self.rebuild_path($path)?.as_path());
+        }
+    };
+}
+
+macro_rules! inject_rename {
+    ($self:ident, $ctx:expr, $rename:ident, $path:expr, $new_path:expr) => {
+        if $self.enable_injection.load(Ordering::SeqCst) {
+            $self.injector.inject_rename(
+                &$ctx,
+                &mut $rename,
+                $self.rebuild_path($path)?.as_path(),
+                $self.rebuild_path($new_path)?.as_path(),
+            );
         }
     };
 }
 
 macro_rules! inject_reply {
-    ($self:ident, $method:ident, $path:expr, $reply:ident, $reply_typ:ident) => {
+    ($self:ident, $ctx:expr, $method:ident, $path:expr, $reply:ident, $reply_typ:ident) => {
         if $self.enable_injection.load(Ordering::SeqCst) {
             $self.injector.inject_reply(
+                &$ctx,
                 &Method::$method,
                 $self.rebuild_path($path)?.as_path(),
                 &mut Reply::$reply_typ(&mut $reply),
@@ -96,6 +171,15 @@ pub struct HookFs {
 
     // map from inode to real path
     inode_map: RwLock<InodeMap>,
+
+    // unsynced changes which will be discarded on power loss
+    journal: Mutex<Journal>,
+    // the changes hold it for reading, so that a power loss doesn't roll back
+    // a change which is half done
+    mutations: RwLock<()>,
+
+    // old state of the recent changes which are not visible yet
+    snapshots: Mutex<Snapshots>,
 }
 
 #[derive(Debug, Deref, DerefMut, From)]
@@ -136,6 +220,7 @@ impl<T> FhMap<T> {
     fn get(&self, key: usize) -> Result<&T> {
         self.0.get(key).ok_or(Error::FhNotFound { fh: key as u64 })
     }
+
     fn get_mut(&mut self, key: usize) -> Result<&mut T> {
         self.0
             .get_mut(key)
@@ -147,6 +232,10 @@ impl<T> FhMap<T> {
 pub struct Dir {
     dir: dir::Dir,
     original_path: PathBuf,
+
+    // the listing is built when the directory is read from the beginning,
+    // and the following requests are served from it
+    entries: Option<DirEntries>,
 }
 
 impl Dir {
@@ -154,6 +243,7 @@ impl Dir {
         Dir {
             dir,
             original_path: path.as_ref().to_owned(),
+            entries: None,
         }
     }
     fn original_path(&self) -> &Path {
@@ -179,20 +269,45 @@ impl std::ops::DerefMut for Dir {
 pub struct File {
     file: fs::File,
     original_path: PathBuf,
+
+    // the flags which the file is opened with
+    flags: i32,
+
+    // a poisoned handle fails with the errno until it's released
+    poisoned: Option<Errno>,
 }
 
 impl File {
-    fn new<P: AsRef<Path>>(file: fs::File, path: P) -> File {
+    fn new<P: AsRef<Path>>(file: fs::File, path: P, flags: i32) -> File {
         File {
             file,
             original_path: path.as_ref().to_owned(),
+            flags,
+            poisoned: None,
         }
     }
-    fn original_path(&self) -> &Path {
-        &self.original_path
+    fn handle(&self, fh: u64) -> Handle {
+        Handle {
+            fh,
+            fd: self.as_raw_fd(),
+            original_path: self.original_path.clone(),
+            flags: self.flags,
+            poisoned: self.poisoned,
+        }
     }
 }
 
+// Handle is a copy of the state of an opened file, so that the opened files
+// don't have to be locked while the handle is checked
+#[derive(Debug)]
+struct Handle {
+    fh: u64,
+    fd: RawFd,
+    original_path: PathBuf,
+    flags: i32,
+    poisoned: Option<Errno>,
+}
+
 impl std::ops::Deref for File {
     type Target = fs::File;
 
@@ -229,6 +344,9 @@ impl HookFs {
             injector,
             inode_map,
             enable_injection: AtomicBool::from(false),
+            journal: Mutex::new(Journal::new(original_path.as_ref().join(HOLDING_DIR))),
+            mutations: RwLock::new(()),
+            snapshots: Mutex::new(Snapshots::default()),
         }
     }
 
@@ -238,6 +356,20 @@ impl HookFs {
 
     pub fn disable_injection(&self) {
         self.enable_injection.store(false, Ordering::SeqCst);
+        self.injector.disable();
+    }
+
+    pub fn trigger(&self) {
+        self.injector.trigger();
+    }
+
+    // power_loss discards the unsynced changes, if a power loss injector is
+    // configured
+    pub fn power_loss(&self) {
+        if let Some(loss) = self.injector.power_loss() {
+            let _mutations = runtime::block_on(self.mutations.write());
+            self.journal.lock().unwrap().rollback(loss);
+        }
     }
 
     pub fn rebuild_path<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
@@ -300,13 +432,353 @@ fn convert_libc_stat_to_fuse_stat(stat: libc::stat) -> Result<FileAttr> {
 }
 
 impl HookFs {
-    async fn get_file_attr(&self, path: &Path) -> Result<FileAttr> {
-        let mut attr = async_stat(&path)
-            .await
-            .map(convert_libc_stat_to_fuse_stat)??;
+    // check_handle fails if the handle has been poisoned, or if any injector
+    // decides to poison it now
+    async fn check_handle(
+        &self,
+        ctx: RequestContext,
+        method: Method,
+        handle: &Handle,
+    ) -> Result<()> {
+        if let Some(errno) = handle.poisoned {
+            return Err(Error::Sys(errno));
+        }
+        if !self.enable_injection.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let path = self.rebuild_path(&handle.original_path)?;
+        let mut poisoned = self.injector.poison(&ctx, &method, &path);
+        if poisoned.is_none() {
+            if let Some(errno) = self.injector.stale(&ctx, &method, &path) {
+                let ino = async_fstat(handle.fd).await?.st_ino;
+                let replaced = match async_stat(&handle.original_path).await {
+                    Ok(stat) => stat.st_ino != ino,
+                    Err(_) => true,
+                };
+                if replaced {
+                    debug!("handle of {} is stale", path.display());
+                    poisoned = Some(errno);
+                }
+            }
+        }
+
+        if let Some(errno) = poisoned {
+            // the opened files are only locked for writing to store the poison
+            if let Ok(file) = self.opened_files.write().await.get_mut(handle.fh as usize) {
+                file.poisoned = Some(errno);
+            }
+            return Err(Error::Sys(errno));
+        }
+        Ok(())
+    }
+
+    // rename_paths performs a rename in the way decided by the injectors. The
+    // inode map is released during the window of a non-atomic rename, so
+    // that the intermediate state can be looked up.
+    async fn rename_paths<'a>(
+        &'a self,
+        path: &Path,
+        new_path: &Path,
+        rename: Rename,
+        inode_map: RwLockWriteGuard<'a, InodeMap>,
+    ) -> Result<RwLockWriteGuard<'a, InodeMap>> {
+        let is_dir = |stat: &stat::FileStat| stat.st_mode & libc::S_IFMT == libc::S_IFDIR;
+        let source = async_stat(path).await?;
+        let destination = async_stat(new_path).await.ok();
+
+        let rename = match (rename, &destination) {
+            (_, Some(dest)) if dest.st_dev == source.st_dev && dest.st_ino == source.st_ino => {
+                trace!("source and destination are the same file, rename it atomically");
+                Rename::Atomic
+            }
+            (Rename::LinkFirst(_), _) if is_dir(&source) => {
+                trace!("directory cannot be linked, rename it atomically");
+                Rename::Atomic
+            }
+            (rename, _) => rename,
+        };
+
+        // the destination is changed before the source is renamed, so the
+        // checks of rename(2) are done in advance
+        if let (false, Some(dest)) = (matches!(rename, Rename::Atomic), &destination) {
+            match (is_dir(&source), is_dir(dest)) {
+                (true, false) => return Err(Error::Sys(Errno::ENOTDIR)),
+                (false, true) => return Err(Error::Sys(Errno::EISDIR)),
+                (true, true) => {
+                    let new_path = new_path.to_path_buf();
+                    let empty = spawn_blocking(move || -> Result<bool> {
+                        Ok(std::fs::read_dir(new_path)?.next().is_none())
+                    })
+                    .await??;
+                    if !empty {
+                        return Err(Error::Sys(Errno::ENOTEMPTY));
+                    }
+                }
+                (false, false) => {}
+            }
+        }
+
+        // the intermediate file is kept in the holding directory, which is
+        // hidden from the mount, so it cannot collide with a visible entry
+        let tmp_path = self.journal.lock().unwrap().hold();
+        if !matches!(rename, Rename::Atomic) {
+            if let Some(holding) = tmp_path.parent() {
+                let holding = holding.to_owned();
+                spawn_blocking(move || std::fs::create_dir_all(holding)).await??;
+            }
+        }
+
+        match rename {
+            Rename::Atomic => {
+                async_renameat(path, new_path).await?;
+                Ok(inode_map)
+            }
+            Rename::RemoveFirst(window) => {
+                // the destination is moved aside instead of removed, so that
+                // it can be restored if the source fails to be renamed
+                if destination.is_some() {
+                    async_renameat(new_path, &tmp_path).await?;
+                }
+                let inode_map = self.wait_window(inode_map, window).await;
+                if let Err(err) = async_renameat(path, new_path).await {
+                    if destination.is_some() {
+                        if let Err(err) = async_renameat(&tmp_path, new_path).await {
+                            error!("fail to restore {}: {:?}", new_path.display(), err);
+                        }
+                    }
+                    return Err(err);
+                }
+
+                let removed = match destination {
+                    Some(dest) if is_dir(&dest) => {
+                        let tmp_path = tmp_path.clone();
+                        spawn_blocking(move || std::fs::remove_dir(tmp_path))
+                            .await?
+                            .map_err(Error::from)
+                    }
+                    Some(_) => async_unlink(&tmp_path).await,
+                    None => Ok(()),
+                };
+                if let Err(err) = removed {
+                    error!("fail to remove {}: {:?}", tmp_path.display(), err);
+                }
+                Ok(inode_map)
+            }
+            Rename::LinkFirst(window) => {
+                // link to a temporary name first, so that the destination is
+                // replaced atomically
+                let path_clone = path.to_path_buf();
+                let tmp_path_clone = tmp_path.clone();
+                spawn_blocking(move || {
+                    linkat(
+                        None,
+                        &path_clone,
+                        None,
+                        &tmp_path_clone,
+                        LinkatFlags::NoSymlinkFollow,
+                    )
+                })
+                .await??;
+                if let Err(err) = async_renameat(&tmp_path, new_path).await {
+                    if let Err(err) = async_unlink(&tmp_path).await {
+                        error!("fail to remove {}: {:?}", tmp_path.display(), err);
+                    }
+                    return Err(err);
+                }
+                let inode_map = self.wait_window(inode_map, window).await;
+                async_unlink(path).await?;
+                Ok(inode_map)
+            }
+        }
+    }
+
+    async fn wait_window<'a>(
+        &'a self,
+        inode_map: RwLockWriteGuard<'a, InodeMap>,
+        window: std::time::Duration,
+    ) -> RwLockWriteGuard<'a, InodeMap> {
+        drop(inode_map);
+        delay_for(window).await;
+        self.inode_map.write().await
+    }
+
+    async fn get_handle(&self, fh: u64) -> Result<Handle> {
+        let opened_files = self.opened_files.read().await;
+        Ok(opened_files.get(fh as usize)?.handle(fh))
+    }
+
+    fn inject_xattr(
+        &self,
+        ctx: RequestContext,
+        method: Method,
+        path: &Path,
+        name: &OsStr,
+    ) -> Result<Option<Vec<u8>>> {
+        if !self.enable_injection.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+
+        self.injector
+            .inject_xattr(&ctx, &method, self.rebuild_path(path)?.as_path(), name)
+    }
+
+    fn lag(
+        &self,
+        ctx: RequestContext,
+        method: Method,
+        path: &Path,
+    ) -> Result<Option<std::time::Duration>> {
+        if !self.enable_injection.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+
+        Ok(self
+            .injector
+            .lag(&ctx, &method, self.rebuild_path(path)?.as_path()))
+    }
+
+    // snapshot saves the attributes of the file, and the content of
+    // [offset, offset + len) before it's changed, if the change should not be
+    // visible for a while
+    async fn snapshot(
+        &self,
+        ctx: RequestContext,
+        method: Method,
+        path: &Path,
+        offset: u64,
+        len: u64,
+    ) -> Result<()> {
+        let window = match self.lag(ctx, method, path)? {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        if !self.snapshots.lock().unwrap().contains(path) {
+            let attr = match async_stat(path).await {
+                Ok(stat) => convert_libc_stat_to_fuse_stat(stat)?,
+                // a phantom is not in the backing filesystem, and has no
+                // state to keep
+                Err(Error::Sys(Errno::ENOENT)) => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            self.snapshots
+                .lock()
+                .unwrap()
+                .insert(path.to_owned(), attr, window);
+        }
+
+        let missing =
+            self.snapshots
+                .lock()
+                .unwrap()
+                .missing(path, offset, offset.saturating_add(len));
+        for (start, end) in missing {
+            let path_clone = path.to_owned();
+            let data = spawn_blocking(move || -> Result<Vec<u8>> {
+                let mut file = std::fs::File::open(path_clone)?;
+                std::io::Seek::seek(&mut file, SeekFrom::Start(start))?;
+                let mut data = Vec::new();
+                std::io::Read::read_to_end(&mut std::io::Read::take(file, end - start), &mut data)?;
+                Ok(data)
+            })
+            .await??;
+            self.snapshots.lock().unwrap().save(path, start, data);
+        }
+        Ok(())
+    }
+
+    // hide_entry hides the new entry from readdir, if it should not be
+    // visible for a while
+    fn hide_entry(&self, ctx: RequestContext, method: Method, path: &Path) -> Result<()> {
+        if let Some(window) = self.lag(ctx, method, path)? {
+            self.snapshots.lock().unwrap().hide(path.to_owned(), window);
+        }
+        Ok(())
+    }
+
+    fn phantom(
+        &self,
+        ctx: RequestContext,
+        method: Method,
+        path: &Path,
+    ) -> Result<Option<FileAttr>> {
+        if !self.enable_injection.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+
+        Ok(self
+            .injector
+            .phantom(&ctx, &method, self.rebuild_path(path)?.as_path()))
+    }
+
+    fn hidden(&self, ctx: RequestContext, method: Method, path: &Path) -> Result<bool> {
+        if path == self.journal.lock().unwrap().holding() {
+            return Ok(true);
+        }
+        Ok(self.enable_injection.load(Ordering::SeqCst)
+            && self
+                .injector
+                .hidden(&ctx, &method, self.rebuild_path(path)?.as_path()))
+    }
+
+    fn should_journal(&self, ctx: RequestContext, method: Method, path: &Path) -> Result<bool> {
+        Ok(self.enable_injection.load(Ordering::SeqCst)
+            && self
+                .injector
+                .journal(&ctx, &method, self.rebuild_path(path)?.as_path()))
+    }
+
+    fn journal(&self, ctx: RequestContext, method: Method, entry: JournalEntry) -> Result<()> {
+        if self.should_journal(ctx, method, entry.path())? {
+            self.journal.lock().unwrap().push(entry);
+        }
+        Ok(())
+    }
+
+    async fn journal_range(
+        &self,
+        ctx: RequestContext,
+        method: Method,
+        path: &Path,
+        offset: u64,
+        len: u64,
+    ) -> Result<()> {
+        if self.should_journal(ctx, method, path)? {
+            let spill = self.journal.lock().unwrap().hold();
+            if let Some(entry) = JournalEntry::range(path, offset, len, spill).await? {
+                self.journal.lock().unwrap().push(entry);
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_file_attr(
+        &self,
+        ctx: RequestContext,
+        method: Method,
+        path: &Path,
+    ) -> Result<FileAttr> {
+        let stale_attr = if self.enable_injection.load(Ordering::SeqCst) {
+            self.snapshots.lock().unwrap().attr(path)
+        } else {
+            None
+        };
+        let mut attr = match stale_attr {
+            Some(attr) => {
+                trace!("return stale attr");
+                attr
+            }
+            None => match async_stat(&path).await {
+                Ok(stat) => convert_libc_stat_to_fuse_stat(stat)?,
+                Err(Error::Sys(Errno::ENOENT)) => self
+                    .phantom(ctx, method, path)?
+                    .ok_or(Error::Sys(Errno::ENOENT))?,
+                Err(err) => return Err(err),
+            },
+        };
 
         trace!("before inject attr {:?}", &attr);
-        inject_attr!(self, attr, path);
+        inject_attr!(self, ctx, attr, path);
         trace!("after inject attr {:?}", &attr);
 
         Ok(attr)
@@ -325,7 +797,7 @@ impl AsyncFileSystemImpl for HookFs {
         trace!("destroy");
     }
 
-    async fn lookup(&self, parent: u64, name: OsString) -> Result<Entry> {
+    async fn lookup(&self, ctx: RequestContext, parent: u64, name: OsString) -> Result<Entry> {
         trace!("lookup");
         let start_time = std::time::Instant::now();
 
@@ -336,9 +808,13 @@ impl AsyncFileSystemImpl for HookFs {
         };
         trace!("lookup in {}", path.display());
 
-        inject!(self, LOOKUP, path.as_path());
+        let _guard = inject!(self, ctx, LOOKUP, path.as_path());
 
-        let stat = self.get_file_attr(&path).await?;
+        if self.hidden(ctx, Method::LOOKUP, &path)? {
+            trace!("{} is hidden", path.display());
+            return Err(Error::Sys(Errno::ENOENT));
+        }
+        let stat = self.get_file_attr(ctx, Method::LOOKUP, &path).await?;
 
         trace!("insert ({}, {}) into inode_map", stat.ino, path.display());
         self.inode_map
@@ -352,18 +828,18 @@ impl AsyncFileSystemImpl for HookFs {
         let finish_time = std::time::Instant::now();
         let mut reply = Entry::new(finish_time - start_time, stat, 0);
         trace!("before inject {:?}", reply);
-        inject_reply!(self, LOOKUP, path.as_path(), reply, Entry);
+        inject_reply!(self, ctx, LOOKUP, path.as_path(), reply, Entry);
         trace!("after inject {:?}", reply);
 
         Ok(reply)
     }
 
-    async fn forget(&self, _ino: u64, _nlookup: u64) {
+    async fn forget(&self, _ctx: RequestContext, _ino: u64, _nlookup: u64) {
         trace!("forget not implemented yet");
         // Maybe hookfs doesn't need forget
     }
 
-    async fn getattr(&self, ino: u64) -> Result<Attr> {
+    async fn getattr(&self, ctx: RequestContext, ino: u64) -> Result<Attr> {
         trace!("getattr");
         let start_time = std::time::Instant::now();
 
@@ -372,16 +848,23 @@ impl AsyncFileSystemImpl for HookFs {
             inode_map.get_path(ino)?.to_owned()
         };
         trace!("getting attr from path {}", path.display());
-        inject!(self, GETATTR, &path);
+        let _guard = inject!(self, ctx, GETATTR, &path);
 
-        let stat = self.get_file_attr(&path).await?;
+        // a hidden file can still be reached by an inode which has been
+        // looked up before
+        if self.hidden(ctx, Method::GETATTR, &path)? {
+            trace!("{} is hidden", path.display());
+            return Err(Error::Sys(Errno::ENOENT));
+        }
+
+        let stat = self.get_file_attr(ctx, Method::GETATTR, &path).await?;
 
         trace!("return with {:?}", stat);
 
         let finish_time = std::time::Instant::now();
         let mut reply = Attr::new(finish_time - start_time, stat);
         trace!("before inject {:?}", reply);
-        inject_reply!(self, GETATTR, path, reply, Attr);
+        inject_reply!(self, ctx, GETATTR, path, reply, Attr);
         trace!("after inject {:?}", reply);
 
         Ok(reply)
@@ -389,6 +872,7 @@ impl AsyncFileSystemImpl for HookFs {
 
     async fn setattr(
         &self,
+        ctx: RequestContext,
         ino: u64,
         mode: Option<u32>,
         uid: Option<u32>,
@@ -411,7 +895,23 @@ impl AsyncFileSystemImpl for HookFs {
             let inode_map = self.inode_map.read().await;
             inode_map.get_path(ino)?.to_owned()
         };
-        inject!(self, SETATTR, &path);
+        let guard = inject!(self, ctx, SETATTR, &path);
+        let mutation = self.mutations.read().await;
+
+        // the original attributes are journaled before any of them is changed
+        let times = matches!(
+            (atime, mtime),
+            (
+                Some(TimeOrNow::SpecificTime(_)),
+                Some(TimeOrNow::SpecificTime(_))
+            )
+        );
+        if (mode.is_some() || uid.is_some() || gid.is_some() || times)
+            && self.should_journal(ctx, Method::SETATTR, &path)?
+        {
+            let entry = JournalEntry::attr(&path).await?;
+            self.journal.lock().unwrap().push(entry);
+        }
 
         async_chown(&path, uid, gid).await?;
 
@@ -420,6 +920,10 @@ impl AsyncFileSystemImpl for HookFs {
         }
 
         if let Some(size) = size {
+            self.journal_range(ctx, Method::SETATTR, &path, size, u64::MAX)
+                .await?;
+            self.snapshot(ctx, Method::SETATTR, &path, size, u64::MAX)
+                .await?;
             async_truncate(&path, size as i64).await?;
         }
 
@@ -444,18 +948,22 @@ impl AsyncFileSystemImpl for HookFs {
             // TODO: check whether one of them is Some
             async_utimes(&path, atime, mtime).await?;
         }
+        inject_post!(self, ctx, SETATTR, &path);
 
-        self.getattr(ino).await
+        // the attributes are got as a new operation
+        drop(mutation);
+        drop(guard);
+        self.getattr(ctx, ino).await
     }
 
-    async fn readlink(&self, ino: u64) -> Result<Data> {
+    async fn readlink(&self, ctx: RequestContext, ino: u64) -> Result<Data> {
         trace!("readlink");
 
         let link_path = {
             let inode_map = self.inode_map.read().await;
             inode_map.get_path(ino)?.to_owned()
         };
-        inject!(self, READLINK, &link_path);
+        let _guard = inject!(self, ctx, READLINK, &link_path);
 
         let path = async_readlink(&link_path).await?;
 
@@ -466,7 +974,7 @@ impl AsyncFileSystemImpl for HookFs {
 
         let mut reply = Data::new(path.into_bytes());
         trace!("before inject {:?}", reply);
-        inject_reply!(self, READLINK, &link_path, reply, Data);
+        inject_reply!(self, ctx, READLINK, &link_path, reply, Link);
         trace!("after inject {:?}", reply);
 
         Ok(reply)
@@ -474,6 +982,7 @@ impl AsyncFileSystemImpl for HookFs {
 
     async fn mknod(
         &self,
+        ctx: RequestContext,
         parent: u64,
         name: OsString,
         mode: u32,
@@ -487,19 +996,37 @@ impl AsyncFileSystemImpl for HookFs {
             let parent_path = inode_map.get_path(parent)?;
             parent_path.join(&name)
         };
-        inject!(self, MKNOD, path.as_path());
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let guard = inject!(self, ctx, MKNOD, path.as_path());
+        let mutation = self.mutations.read().await;
+        let cpath = CString::new(path.as_os_str().as_bytes())?;
 
-        trace!("mknod for {:?}", path);
+        trace!("mknod for {:?}", cpath);
 
-        let ret = async_mknod(path, mode, rdev as u64).await?;
+        let ret = async_mknod(cpath, mode, rdev as u64).await?;
         if ret == -1 {
             return Err(Error::last());
         }
-        self.lookup(parent, name).await
+        self.journal(
+            ctx,
+            Method::MKNOD,
+            JournalEntry::Create { path: path.clone() },
+        )?;
+        self.hide_entry(ctx, Method::MKNOD, &path)?;
+        inject_post!(self, ctx, MKNOD, path.as_path());
+        // the entry is looked up as a new operation
+        drop(mutation);
+        drop(guard);
+        self.lookup(ctx, parent, name).await
     }
 
-    async fn mkdir(&self, parent: u64, name: OsString, _umask: u32, mode: u32) -> Result<Entry> {
+    async fn mkdir(
+        &self,
+        ctx: RequestContext,
+        parent: u64,
+        name: OsString,
+        _umask: u32,
+        mode: u32,
+    ) -> Result<Entry> {
         trace!("mkdir");
 
         let path = {
@@ -507,14 +1034,24 @@ impl AsyncFileSystemImpl for HookFs {
             let parent_path = inode_map.get_path(parent)?;
             parent_path.join(&name)
         };
-        inject!(self, MKDIR, path.as_path());
+        let guard = inject!(self, ctx, MKDIR, path.as_path());
+        let mutation = self.mutations.read().await;
 
         let mode = stat::Mode::from_bits_truncate(mode);
         async_mkdir(&path, mode).await?;
-        self.lookup(parent, name).await
+        self.journal(
+            ctx,
+            Method::MKDIR,
+            JournalEntry::Create { path: path.clone() },
+        )?;
+        self.hide_entry(ctx, Method::MKDIR, &path)?;
+        inject_post!(self, ctx, MKDIR, path.as_path());
+        drop(mutation);
+        drop(guard);
+        self.lookup(ctx, parent, name).await
     }
 
-    async fn unlink(&self, parent: u64, name: OsString) -> Result<()> {
+    async fn unlink(&self, ctx: RequestContext, parent: u64, name: OsString) -> Result<()> {
         trace!("unlink");
 
         let path = {
@@ -522,18 +1059,30 @@ impl AsyncFileSystemImpl for HookFs {
             let parent_path = inode_map.get_path(parent)?;
             parent_path.join(name)
         };
-        inject!(self, UNLINK, path.as_path());
+        let _guard = inject!(self, ctx, UNLINK, path.as_path());
+        let _mutation = self.mutations.read().await;
 
-        let stat = self.get_file_attr(&path).await?;
+        let stat = self.get_file_attr(ctx, Method::UNLINK, &path).await?;
         trace!("remove {} from inode_map", &stat.ino);
         self.inode_map.write().await.remove_path(&stat.ino, &path);
 
+        let entry = if self.should_journal(ctx, Method::UNLINK, &path)? {
+            let held = self.journal.lock().unwrap().hold();
+            JournalEntry::unlink(&path, held).await?
+        } else {
+            None
+        };
+
         trace!("unlinking {}", path.display());
         async_unlink(&path).await?;
+        if let Some(entry) = entry {
+            self.journal.lock().unwrap().push(entry);
+        }
+        inject_post!(self, ctx, UNLINK, path.as_path());
         Ok(())
     }
 
-    async fn rmdir(&self, parent: u64, name: OsString) -> Result<()> {
+    async fn rmdir(&self, ctx: RequestContext, parent: u64, name: OsString) -> Result<()> {
         trace!("rmdir");
 
         let path = {
@@ -541,20 +1090,36 @@ impl AsyncFileSystemImpl for HookFs {
             let parent_path = inode_map.get_path(parent)?;
             parent_path.join(name)
         };
-        inject!(self, RMDIR, path.as_path());
+        let _guard = inject!(self, ctx, RMDIR, path.as_path());
+        let _mutation = self.mutations.read().await;
 
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let entry = if self.should_journal(ctx, Method::RMDIR, &path)? {
+            Some(JournalEntry::rmdir(&path).await?)
+        } else {
+            None
+        };
+
+        let cpath = CString::new(path.as_os_str().as_bytes())?;
 
-        let ret = async_rmdir(path).await?;
+        let ret = async_rmdir(cpath).await?;
 
         if ret == -1 {
-            Err(Error::last())
-        } else {
-            Ok(())
+            return Err(Error::last());
         }
+        if let Some(entry) = entry {
+            self.journal.lock().unwrap().push(entry);
+        }
+        inject_post!(self, ctx, RMDIR, path.as_path());
+        Ok(())
     }
 
-    async fn symlink(&self, parent: u64, name: OsString, link: PathBuf) -> Result<Entry> {
+    async fn symlink(
+        &self,
+        ctx: RequestContext,
+        parent: u64,
+        name: OsString,
+        link: PathBuf,
+    ) -> Result<Entry> {
         trace!("symlink");
 
         let path = {
@@ -562,17 +1127,29 @@ impl AsyncFileSystemImpl for HookFs {
             let parent_path = inode_map.get_path(parent)?;
             parent_path.join(&name)
         };
-        inject!(self, SYMLINK, path.as_path());
+        let guard = inject!(self, ctx, SYMLINK, path.as_path());
+        let mutation = self.mutations.read().await;
 
         trace!("create symlink: {} => {}", path.display(), link.display());
 
-        spawn_blocking(move || symlinkat(&link, None, &path)).await??;
-
-        self.lookup(parent, name).await
+        let path_clone = path.clone();
+        spawn_blocking(move || symlinkat(&link, None, &path_clone)).await??;
+        self.journal(
+            ctx,
+            Method::SYMLINK,
+            JournalEntry::Create { path: path.clone() },
+        )?;
+        self.hide_entry(ctx, Method::SYMLINK, &path)?;
+        inject_post!(self, ctx, SYMLINK, path.as_path());
+
+        drop(mutation);
+        drop(guard);
+        self.lookup(ctx, parent, name).await
     }
 
     async fn rename(
         &self,
+        ctx: RequestContext,
         parent: u64,
         name: OsString,
         newparent: u64,
@@ -581,31 +1158,65 @@ impl AsyncFileSystemImpl for HookFs {
     ) -> Result<()> {
         trace!("rename");
 
-        let mut inode_map = self.inode_map.write().await;
-        let parent_path = inode_map.get_path(parent)?;
-        let path = parent_path.join(&name);
+        let (path, new_path) = {
+            let inode_map = self.inode_map.read().await;
+            (
+                inode_map.get_path(parent)?.join(&name),
+                inode_map.get_path(newparent)?.join(&newname),
+            )
+        };
         trace!("get original path: {}", path.display());
-        inject!(self, RENAME, path.as_path());
-
-        let new_parent_path = inode_map.get_path(newparent)?;
-        let new_path = new_parent_path.join(&newname);
+        let _guard = inject!(self, ctx, RENAME, path.as_path());
+        let _mutation = self.mutations.read().await;
 
         trace!("get new path: {}", new_path.display());
 
-        trace!("rename from {} to {}", path.display(), new_path.display());
-
-        let new_path_clone = new_path.clone();
-        spawn_blocking(move || renameat(None, &path, None, &new_path_clone)).await??;
-
-        let stat = self.get_file_attr(&new_path).await?;
+        let mut rename = Rename::Atomic;
+        inject_rename!(self, ctx, rename, &path, &new_path);
+
+        // the inode map is locked for the rest of the rename, so that the
+        // paths stay valid until the new path is inserted. It's not locked
+        // while injecting, as an injector may wait for other operations.
+        let inode_map = self.inode_map.write().await;
+        let path = inode_map.get_path(parent)?.join(&name);
+        let new_path = inode_map.get_path(newparent)?.join(&newname);
+
+        trace!(
+            "rename from {} to {} {:?}",
+            path.display(),
+            new_path.display(),
+            rename
+        );
+        let mut inode_map = self
+            .rename_paths(&path, &new_path, rename, inode_map)
+            .await?;
+        self.journal(
+            ctx,
+            Method::RENAME,
+            JournalEntry::Rename {
+                from: path,
+                to: new_path.clone(),
+            },
+        )?;
+
+        let stat = self.get_file_attr(ctx, Method::RENAME, &new_path).await?;
 
         trace!("insert ({}, {})", stat.ino, new_path.display());
-        inode_map.insert_path(stat.ino, new_path);
+        inode_map.insert_path(stat.ino, new_path.clone());
+        drop(inode_map);
+
+        inject_post!(self, ctx, RENAME, new_path.as_path());
 
         Ok(())
     }
 
-    async fn link(&self, ino: u64, newparent: u64, newname: OsString) -> Result<Entry> {
+    async fn link(
+        &self,
+        ctx: RequestContext,
+        ino: u64,
+        newparent: u64,
+        newname: OsString,
+    ) -> Result<Entry> {
         trace!("link");
         {
             let (original_path, new_parent_path) = {
@@ -617,7 +1228,8 @@ impl AsyncFileSystemImpl for HookFs {
             };
 
             let new_path = new_parent_path.join(&newname);
-            inject!(self, LINK, new_path.as_path());
+            let _guard = inject!(self, ctx, LINK, new_path.as_path());
+            let _mutation = self.mutations.read().await;
 
             trace!(
                 "link from {} to {}",
@@ -625,21 +1237,31 @@ impl AsyncFileSystemImpl for HookFs {
                 original_path.display()
             );
 
+            let new_path_clone = new_path.clone();
             spawn_blocking(move || {
                 linkat(
                     None,
                     &original_path,
                     None,
-                    &new_path,
+                    &new_path_clone,
                     LinkatFlags::NoSymlinkFollow,
                 )
             })
             .await??;
+            self.journal(
+                ctx,
+                Method::LINK,
+                JournalEntry::Create {
+                    path: new_path.clone(),
+                },
+            )?;
+            self.hide_entry(ctx, Method::LINK, &new_path)?;
+            inject_post!(self, ctx, LINK, new_path.as_path());
         }
-        self.lookup(newparent, newname).await
+        self.lookup(ctx, newparent, newname).await
     }
 
-    async fn open(&self, ino: u64, flags: i32) -> Result<Open> {
+    async fn open(&self, ctx: RequestContext, ino: u64, flags: i32) -> Result<Open> {
         trace!("open");
         // TODO: support direct io
         if flags & libc::O_DIRECT != 0 {
@@ -654,24 +1276,42 @@ impl AsyncFileSystemImpl for HookFs {
             let inode_map = self.inode_map.read().await;
             inode_map.get_path(ino)?.to_owned()
         };
-        inject!(self, OPEN, &path);
+        let ctx = ctx.with_flags(flags);
+        let _guard = inject!(self, ctx, OPEN, &path);
+        if self.hidden(ctx, Method::OPEN, &path)? {
+            trace!("{} is hidden", path.display());
+            return Err(Error::Sys(Errno::ENOENT));
+        }
 
         trace!("open with flags: {:?}", filtered_flags);
 
-        let fd = async_open(&path, filtered_flags, stat::Mode::S_IRWXU).await?;
+        let fd = match async_open(&path, filtered_flags, stat::Mode::S_IRWXU).await {
+            Ok(fd) => fd,
+            Err(Error::Sys(Errno::ENOENT)) => match self.phantom(ctx, Method::OPEN, &path)? {
+                // the content of a phantom is served by the injector, and the
+                // writes are discarded
+                Some(_) => {
+                    trace!("open phantom {}", path.display());
+                    async_open(Path::new("/dev/null"), OFlag::O_RDWR, stat::Mode::empty()).await?
+                }
+                None => return Err(Error::Sys(Errno::ENOENT)),
+            },
+            Err(err) => return Err(err),
+        };
 
         let std_file = unsafe { std::fs::File::from_raw_fd(fd) };
         let file = fs::File::from_std(std_file);
-        let fh = self.opened_files.write().await.insert(File {
-            file,
-            original_path: path.clone(),
-        }) as u64;
+        let fh = self
+            .opened_files
+            .write()
+            .await
+            .insert(File::new(file, &path, flags)) as u64;
 
         trace!("return with fh: {}, flags: {}", fh, 0);
 
         let mut reply = Open::new(fh, 0);
         trace!("before inject {:?}", reply);
-        inject_reply!(self, OPEN, path, reply, Open);
+        inject_reply!(self, ctx, OPEN, path, reply, Open);
         trace!("after inject {:?}", reply);
         // TODO: force DIRECT_IO is not a great option
         Ok(reply)
@@ -679,6 +1319,7 @@ impl AsyncFileSystemImpl for HookFs {
 
     async fn read(
         &self,
+        ctx: RequestContext,
         _ino: u64,
         fh: u64,
         offset: i64,
@@ -688,37 +1329,62 @@ impl AsyncFileSystemImpl for HookFs {
     ) -> Result<Data> {
         trace!("read");
 
-        let mut opened_files = self.opened_files.write().await;
-        let file = opened_files.get_mut(fh as usize)?;
-        inject!(self, READ, &file.original_path());
-
-        trace!("seek to {}", offset);
-        file.seek(SeekFrom::Start(offset as u64)).await?;
+        let handle = self.get_handle(fh).await?;
+        let ctx = ctx.with_flags(handle.flags);
+        self.check_handle(ctx, Method::READ, &handle).await?;
 
-        let mut buf = Vec::new();
-        buf.resize(size as usize, 0);
+        // the opened files are not locked while the operation is injected,
+        // so that a parked operation doesn't block the other files
+        let path = handle.original_path.as_path();
+        let _guard = inject!(self, ctx, READ, path);
 
-        trace!("read exact");
-        match file.read_exact(&mut buf).await {
-            Ok(_) => {}
-            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
-                trace!("read eof");
+        let content = if self.enable_injection.load(Ordering::SeqCst) {
+            self.injector
+                .content(&ctx, &Method::READ, &self.rebuild_path(path)?)
+        } else {
+            None
+        };
+        let buf = match content {
+            Some(content) => {
+                let start = std::cmp::min(offset as usize, content.len());
+                let end = std::cmp::min(start + size as usize, content.len());
+                content[start..end].to_owned()
             }
-            Err(err) => {
-                error!("unknown error: {}", err);
-                return Err(err.into());
+            None => {
+                trace!("read {} bytes at {}", size, offset);
+                let buf = async_pread(handle.fd, offset, size as usize).await?;
+
+                let stale_data = if self.enable_injection.load(Ordering::SeqCst) {
+                    self.snapshots
+                        .lock()
+                        .unwrap()
+                        .read(path, offset as u64, size as usize, &buf)
+                } else {
+                    None
+                };
+                match stale_data {
+                    Some(data) => {
+                        trace!("return stale data");
+                        data
+                    }
+                    None => buf,
+                }
             }
-        }
+        };
 
         let mut reply = Data::new(buf);
         trace!("before inject DATA[{:?}]", reply.data.len());
-        inject_reply!(self, READ, &file.original_path(), reply, Data);
+        inject_reply!(self, ctx, READ, path, reply, Data);
         trace!("after inject DATA[{:?}]", reply.data.len());
+
+        // only the returned bytes are charged
+        inject_io!(self, ctx, READ, path, offset, reply.data.len());
         Ok(reply)
     }
 
     async fn write(
         &self,
+        ctx: RequestContext,
         _ino: u64,
         fh: u64,
         offset: i64,
@@ -729,39 +1395,65 @@ impl AsyncFileSystemImpl for HookFs {
     ) -> Result<Write> {
         trace!("write");
 
-        let mut opened_files = self.opened_files.write().await;
-        let file = opened_files.get_mut(fh as usize)?;
-        inject!(self, WRITE, file.original_path());
-
-        file.seek(SeekFrom::Start(offset as u64)).await?;
+        let handle = self.get_handle(fh).await?;
+        let ctx = ctx.with_flags(handle.flags);
+        self.check_handle(ctx, Method::WRITE, &handle).await?;
 
-        file.write_all(&data).await?;
+        let path = handle.original_path.as_path();
+        let _guard = inject!(self, ctx, WRITE, path);
+        let _mutation = self.mutations.read().await;
 
+        // the reply is injected before writing, so that only the reported
+        // bytes are persisted
         let mut reply = Write::new(data.len() as u32);
         trace!("before inject {:?}", reply);
-        inject_reply!(self, WRITE, file.original_path(), reply, Write);
+        inject_reply!(self, ctx, WRITE, path, reply, Write);
         trace!("after inject {:?}", reply);
+
+        let mut data = data;
+        data.truncate(reply.size as usize);
+
+        let mut write_data = WriteData::new(offset, data);
+        inject_write!(self, ctx, write_data, path);
+
+        // only the bytes which will be written are charged, and all of them
+        // are charged before writing
+        for (offset, data) in write_data.chunks.iter() {
+            inject_io!(self, ctx, WRITE, path, *offset, data.len());
+        }
+
+        for (offset, data) in write_data.chunks {
+            self.snapshot(ctx, Method::WRITE, path, offset as u64, data.len() as u64)
+                .await?;
+            self.journal_range(ctx, Method::WRITE, path, offset as u64, data.len() as u64)
+                .await?;
+
+            trace!("write {} bytes at {}", data.len(), offset);
+            async_pwrite(handle.fd, offset, data).await?;
+        }
+        inject_post!(self, ctx, WRITE, path);
+
         Ok(reply)
     }
 
-    async fn flush(&self, _ino: u64, fh: u64, _lock_owner: u64) -> Result<()> {
+    async fn flush(&self, ctx: RequestContext, _ino: u64, fh: u64, _lock_owner: u64) -> Result<()> {
         trace!("flush");
 
         // flush is implemented with fsync. Is it the correct way?
-        let fd: RawFd = {
-            let opened_files = self.opened_files.read().await;
-            let file = opened_files.get(fh as usize)?;
-
-            inject!(self, FLUSH, file.original_path());
+        let handle = self.get_handle(fh).await?;
+        let ctx = ctx.with_flags(handle.flags);
+        self.check_handle(ctx, Method::FLUSH, &handle).await?;
+        let _guard = inject!(self, ctx, FLUSH, &handle.original_path);
 
-            file.as_raw_fd()
-        };
+        let fd = handle.fd;
         spawn_blocking(move || fsync(fd)).await??;
+        inject_post!(self, ctx, FLUSH, &handle.original_path);
         Ok(())
     }
 
     async fn release(
         &self,
+        _ctx: RequestContext,
         _ino: u64,
         fh: u64,
         _flags: i32,
@@ -772,34 +1464,38 @@ impl AsyncFileSystemImpl for HookFs {
 
         let mut opened_files = self.opened_files.write().await;
         opened_files.remove(fh as usize);
+        self.injector.release(fh);
         Ok(())
     }
 
-    async fn fsync(&self, _ino: u64, fh: u64, _datasync: bool) -> Result<()> {
+    async fn fsync(&self, ctx: RequestContext, _ino: u64, fh: u64, _datasync: bool) -> Result<()> {
         trace!("fsync");
 
-        let fd: RawFd = {
-            let opened_files = self.opened_files.read().await;
-            let file = opened_files.get(fh as usize)?;
-
-            inject!(self, FLUSH, file.original_path());
-
-            file.as_raw_fd()
-        };
+        let handle = self.get_handle(fh).await?;
+        let ctx = ctx.with_flags(handle.flags);
+        self.check_handle(ctx, Method::FLUSH, &handle).await?;
+        let _guard = inject!(self, ctx, FLUSH, &handle.original_path);
 
+        let fd = handle.fd;
         spawn_blocking(move || fsync(fd)).await??;
+        self.journal.lock().unwrap().sync(&handle.original_path);
+        inject_post!(self, ctx, FLUSH, &handle.original_path);
 
         Ok(())
     }
 
-    async fn opendir(&self, ino: u64, flags: i32) -> Result<Open> {
+    async fn opendir(&self, ctx: RequestContext, ino: u64, flags: i32) -> Result<Open> {
         trace!("opendir");
 
         let path = {
             let inode_map = self.inode_map.read().await;
             inode_map.get_path(ino)?.to_owned()
         };
-        inject!(self, OPENDIR, &path);
+        let _guard = inject!(self, ctx, OPENDIR, &path);
+        if self.hidden(ctx, Method::OPENDIR, &path)? {
+            trace!("{} is hidden", path.display());
+            return Err(Error::Sys(Errno::ENOENT));
+        }
 
         let filtered_flags = flags & (!libc::O_APPEND);
         let filtered_flags = OFlag::from_bits_truncate(filtered_flags as i32);
@@ -815,127 +1511,231 @@ impl AsyncFileSystemImpl for HookFs {
 
         let mut reply = Open::new(fh, flags);
         trace!("before inject {:?}", reply);
-        inject_reply!(self, OPENDIR, &path, reply, Open);
+        inject_reply!(self, ctx, OPENDIR, &path, reply, Open);
         trace!("after inject {:?}", reply);
         Ok(reply)
     }
 
-    async fn readdir(&self, _ino: u64, fh: u64, offset: i64, mut reply: ReplyDirectory) {
+    async fn readdir(
+        &self,
+        ctx: RequestContext,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
         trace!("readdir");
 
         let offset = offset as usize;
 
-        // TODO: optimize the implementation
-        let (parent_path, all_entries): (PathBuf, Vec<_>) = {
-            let mut opened_dirs = self.opened_dirs.write().await;
-            let dir = match opened_dirs.get_mut(fh as usize) {
-                Ok(dir) => dir,
+        let parent_path = match self.opened_dirs.read().await.get(fh as usize) {
+            Ok(dir) => dir.original_path().to_owned(),
+            Err(err) => {
+                reply.error(err.into());
+                return;
+            }
+        };
+        let rebuilt_path = match self.rebuild_path(&parent_path) {
+            Ok(path) => path,
+            Err(err) => {
+                error!("fail to rebuild path {}", err);
+                reply.error(err.into());
+                return;
+            }
+        };
+
+        // the opened directories are not locked while the operation is
+        // injected, so that a parked operation doesn't block the others
+        if let Err(err) = self
+            .injector
+            .inject(&ctx, &Method::READDIR, rebuilt_path.as_path())
+            .await
+        {
+            reply.error(err.into());
+            return;
+        }
+        let _guard = if self.enable_injection.load(Ordering::SeqCst) {
+            match self
+                .injector
+                .admit(&ctx, &Method::READDIR, rebuilt_path.as_path())
+                .await
+            {
+                Ok(guard) => guard,
                 Err(err) => {
                     reply.error(err.into());
                     return;
                 }
-            };
+            }
+        } else {
+            None
+        };
 
-            let parent_path = dir.original_path().to_owned();
-            let rebuilt_path = match self.rebuild_path(&parent_path) {
-                Ok(path) => path,
+        let all_entries: Vec<_> = {
+            let mut opened_dirs = self.opened_dirs.write().await;
+            let dir = match opened_dirs.get_mut(fh as usize) {
+                Ok(dir) => dir,
                 Err(err) => {
-                    error!("fail to rebuild path {}", err);
                     reply.error(err.into());
                     return;
                 }
             };
-            if let Err(err) = self
-                .injector
-                .inject(&Method::READDIR, rebuilt_path.as_path())
-                .await
-            {
-                reply.error(err.into());
-                return;
+
+            if offset == 0 || dir.entries.is_none() {
+                let mut entries: Vec<_> = dir
+                    .iter()
+                    .map(|entry| {
+                        let entry = entry.map_err(|err| err.as_errno().unwrap_or(Errno::EIO))?;
+                        let kind = match entry.file_type() {
+                            Some(file_type) => convert_filetype(file_type),
+                            None => {
+                                debug!("unknown file type {:?}", entry.file_type());
+                                return Err(Errno::EINVAL);
+                            }
+                        };
+                        let name = OsStr::from_bytes(entry.file_name().to_bytes()).to_owned();
+                        Ok(DirEntry::new(entry.ino(), kind, name))
+                    })
+                    .collect();
+                let holding = self.journal.lock().unwrap().holding().to_owned();
+                entries.retain(|entry| match entry {
+                    Ok(entry) => parent_path.join(&entry.name) != holding,
+                    Err(_) => true,
+                });
+                if self.enable_injection.load(Ordering::SeqCst) {
+                    let mut snapshots = self.snapshots.lock().unwrap();
+                    entries.retain(|entry| match entry {
+                        Ok(entry) => !snapshots.is_hidden(&parent_path.join(&entry.name)),
+                        Err(_) => true,
+                    });
+                }
+                let mut entries = DirEntries::new(entries);
+
+                trace!("before inject {:?}", entries);
+                if self.enable_injection.load(Ordering::SeqCst) {
+                    if let Err(err) = self.injector.inject_reply(
+                        &ctx,
+                        &Method::READDIR,
+                        rebuilt_path.as_path(),
+                        &mut Reply::DirEntries(&mut entries),
+                    ) {
+                        reply.error(err.into());
+                        return;
+                    }
+                }
+                trace!("after inject {:?}", entries);
+
+                dir.entries = Some(entries);
             }
 
-            (parent_path, dir.iter().collect())
+            match &dir.entries {
+                Some(entries) => entries.entries.iter().skip(offset).cloned().collect(),
+                None => Vec::new(),
+            }
         };
-        if offset >= all_entries.len() {
+        if all_entries.is_empty() {
             trace!("empty reply");
             reply.ok();
             return;
         }
-        for (index, entry) in all_entries.iter().enumerate().skip(offset as usize) {
+        let mut returned = 0;
+        for (index, entry) in all_entries.into_iter().enumerate() {
+            let index = offset + index;
             let entry = match entry {
                 Ok(entry) => entry,
-                Err(err) => {
-                    trace!("return with error: {}", err);
-                    let errno = err.as_errno().map(|errno| errno as i32).unwrap_or(-1);
-                    reply.error(errno);
+                Err(errno) if index == offset => {
+                    trace!("return with error: {}", errno);
+                    reply.error(errno as i32);
                     return;
                 }
-            };
-
-            let name = entry.file_name();
-            let name = OsStr::from_bytes(name.to_bytes());
-
-            let file_type = match entry.file_type() {
-                Some(file_type) => convert_filetype(file_type),
-                None => {
-                    debug!("unknown file type {:?}", entry.file_type());
-                    reply.error(libc::EINVAL);
-                    return;
+                Err(_) => {
+                    // the error is returned by the next request
+                    trace!("stop before error");
+                    break;
                 }
             };
 
-            let path = parent_path.join(name);
-            trace!(
-                "insert ({}, {}) into inode_map",
-                entry.ino(),
-                path.display()
-            );
-            self.inode_map.write().await.insert_path(entry.ino(), path);
+            let path = parent_path.join(&entry.name);
+            trace!("insert ({}, {}) into inode_map", entry.ino, path.display());
+            self.inode_map.write().await.insert_path(entry.ino, path);
 
-            if !reply.add(entry.ino(), (index + 1) as i64, file_type, name) {
+            if !reply.add(entry.ino, (index + 1) as i64, entry.kind, &entry.name) {
                 trace!("add file {:?}", entry);
+                returned += 1;
             } else {
                 trace!("buffer is full");
                 break;
             }
         }
 
+        // only the entries in the reply are charged
+        if self.enable_injection.load(Ordering::SeqCst) && returned > 0 {
+            if let Err(err) = self
+                .injector
+                .inject_io(
+                    &ctx,
+                    &Method::READDIR,
+                    rebuilt_path.as_path(),
+                    offset as i64,
+                    returned,
+                )
+                .await
+            {
+                reply.error(err.into());
+                return;
+            }
+        }
+
         trace!("iterated all files");
         reply.ok();
     }
 
-    async fn releasedir(&self, _ino: u64, fh: u64, _flags: i32) -> Result<()> {
+    async fn releasedir(
+        &self,
+        _ctx: RequestContext,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+    ) -> Result<()> {
         trace!("releasedir");
 
         // FIXME: please implement releasedir
         self.opened_dirs.write().await.remove(fh as usize);
+        self.injector.release(fh);
         Ok(())
     }
 
-    async fn fsyncdir(&self, ino: u64, _fh: u64, _datasync: bool) -> Result<()> {
+    async fn fsyncdir(
+        &self,
+        _ctx: RequestContext,
+        ino: u64,
+        _fh: u64,
+        _datasync: bool,
+    ) -> Result<()> {
         // TODO: inject
 
         let path = {
             let inode_map = self.inode_map.read().await;
             inode_map.get_path(ino)?.to_owned()
         };
+        let path_clone = path.clone();
         spawn_blocking(move || -> Result<_> {
-            std::fs::File::open(path)?.sync_all()?;
+            std::fs::File::open(path_clone)?.sync_all()?;
 
             Ok(())
         })
         .await??;
+        self.journal.lock().unwrap().sync_dir(&path);
         Ok(())
     }
 
-    async fn statfs(&self, ino: u64) -> Result<StatFs> {
+    async fn statfs(&self, ctx: RequestContext, ino: u64) -> Result<StatFs> {
         trace!("statfs");
 
         let path = {
             let inode_map = self.inode_map.read().await;
             inode_map.get_path(ino)?.to_owned()
         };
-        inject!(self, STATFS, &path);
+        let _guard = inject!(self, ctx, STATFS, &path);
 
         let origin_path = self.original_path.clone();
         let stat = spawn_blocking(move || statfs::statfs(&origin_path)).await??;
@@ -951,7 +1751,7 @@ impl AsyncFileSystemImpl for HookFs {
             stat.block_size() as u32,
         );
         trace!("before inject {:?}", reply);
-        inject_reply!(self, STATFS, &path, reply, StatFs);
+        inject_reply!(self, ctx, STATFS, &path, reply, StatFs);
         trace!("after inject {:?}", reply);
 
         Ok(reply)
@@ -959,6 +1759,7 @@ impl AsyncFileSystemImpl for HookFs {
 
     async fn setxattr(
         &self,
+        ctx: RequestContext,
         ino: u64,
         name: OsString,
         value: Vec<u8>,
@@ -971,14 +1772,21 @@ impl AsyncFileSystemImpl for HookFs {
             let inode_map = self.inode_map.read().await;
             inode_map.get_path(ino)?.to_owned()
         };
-        inject!(self, SETXATTR, &path);
+        let _guard = inject!(self, ctx, SETXATTR, &path);
+        let _mutation = self.mutations.read().await;
+        self.inject_xattr(ctx, Method::SETXATTR, &path, &name)?;
+        let entry = if self.should_journal(ctx, Method::SETXATTR, &path)? {
+            Some(JournalEntry::xattr(&path, &name).await?)
+        } else {
+            None
+        };
 
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let cpath = CString::new(path.as_os_str().as_bytes())?;
 
         let name = CString::new(name.as_bytes())?;
 
         let ret = spawn_blocking(move || {
-            let path_ptr = &path.as_bytes_with_nul()[0] as *const u8 as *const libc::c_char;
+            let path_ptr = &cpath.as_bytes_with_nul()[0] as *const u8 as *const libc::c_char;
             let name_ptr = &name.as_bytes_with_nul()[0] as *const u8 as *const libc::c_char;
             let value_ptr = &value[0] as *const u8 as *const libc::c_void;
             unsafe { lsetxattr(path_ptr, name_ptr, value_ptr, value.len(), flags as i32) }
@@ -988,14 +1796,35 @@ impl AsyncFileSystemImpl for HookFs {
         if ret == -1 {
             return Err(Error::last());
         }
+        if let Some(entry) = entry {
+            self.journal.lock().unwrap().push(entry);
+        }
+        inject_post!(self, ctx, SETXATTR, &path);
         Ok(())
     }
 
-    async fn getxattr(&self, ino: u64, name: OsString, size: u32) -> Result<Xattr> {
+    async fn getxattr(
+        &self,
+        ctx: RequestContext,
+        ino: u64,
+        name: OsString,
+        size: u32,
+    ) -> Result<Xattr> {
         trace!("getxattr");
         let inode_map = self.inode_map.read().await;
         let path = inode_map.get_path(ino)?;
-        inject!(self, GETXATTR, path);
+        let _guard = inject!(self, ctx, GETXATTR, path);
+
+        if let Some(value) = self.inject_xattr(ctx, Method::GETXATTR, path, &name)? {
+            trace!("return with overridden value {:?}", value);
+            return if size == 0 {
+                Ok(Xattr::size(value.len() as u32))
+            } else if value.len() > size as usize {
+                Err(Error::Sys(Errno::ERANGE))
+            } else {
+                Ok(Xattr::data(value))
+            };
+        }
 
         let cpath = CString::new(path.as_os_str().as_bytes())?;
 
@@ -1024,69 +1853,92 @@ impl AsyncFileSystemImpl for HookFs {
             trace!("return with size {}", ret);
             Xattr::size(ret as u32)
         } else {
-            trace!("return with data {:?}", shared_buf.as_slice());
-            Xattr::data(shared_buf.as_slice().to_owned())
+            let data = &shared_buf[..ret as usize];
+            trace!("return with data {:?}", data);
+            Xattr::data(data.to_owned())
         };
         trace!("before inject {:?}", reply);
-        inject_reply!(self, GETXATTR, path, reply, Xattr);
+        inject_reply!(self, ctx, GETXATTR, path, reply, Xattr);
         trace!("after inject {:?}", reply);
 
         Ok(reply)
     }
 
-    async fn listxattr(&self, ino: u64, size: u32) -> Result<Xattr> {
+    async fn listxattr(&self, ctx: RequestContext, ino: u64, size: u32) -> Result<Xattr> {
         trace!("listxattr");
         let path = {
             let inode_map = self.inode_map.read().await;
             inode_map.get_path(ino)?.to_owned()
         };
-        inject!(self, LISTXATTR, &path);
+        let _guard = inject!(self, ctx, LISTXATTR, &path);
 
         let cpath = CString::new(path.as_os_str().as_bytes())?;
 
-        let mut buf = Vec::new();
-        buf.resize(size as usize, 0u8);
-
-        let shared_buf = std::sync::Arc::new(buf);
-        let buf_clone = shared_buf.clone();
+        // the whole list is fetched even for a size probe, so that the size
+        // is measured after the list is injected
+        let list = spawn_blocking(move || -> Result<Vec<u8>> {
+            let list = |buf: &mut [u8]| {
+                let ret = unsafe {
+                    llistxattr(
+                        cpath.as_ptr(),
+                        buf.as_mut_ptr() as *mut libc::c_char,
+                        buf.len(),
+                    )
+                };
+                match ret {
+                    -1 => Err(Errno::last()),
+                    len => Ok(len as usize),
+                }
+            };
 
-        let ret = spawn_blocking(move || {
-            let path_ptr = &cpath.as_bytes_with_nul()[0] as *const u8 as *const libc::c_char;
-            let buf_ptr = buf_clone.as_slice() as *const [u8] as *mut [u8] as *mut libc::c_char;
-            unsafe { llistxattr(path_ptr, buf_ptr, size as usize) }
+            // the list may grow between the probe and the fetch
+            loop {
+                let mut buf = vec![0u8; list(&mut []).map_err(Error::Sys)?];
+                match list(&mut buf) {
+                    Ok(len) => {
+                        buf.truncate(len);
+                        break Ok(buf);
+                    }
+                    Err(Errno::ERANGE) => continue,
+                    Err(errno) => break Err(Error::Sys(errno)),
+                }
+            }
         })
-        .await?;
-
-        if ret == -1 {
-            return Err(Error::last());
-        }
+        .await??;
 
-        let mut reply = if size == 0 {
-            Xattr::size(ret as u32)
-        } else {
-            Xattr::data(shared_buf.as_slice().to_owned())
-        };
+        let mut reply = Xattr::data(list);
         trace!("before inject {:?}", reply);
-        inject_reply!(self, LISTXATTR, path, reply, Xattr);
+        inject_reply!(self, ctx, LISTXATTR, path, reply, Xattr);
         trace!("after inject {:?}", reply);
 
-        Ok(reply)
+        match reply {
+            Xattr::Data { data } if size == 0 => Ok(Xattr::size(data.len() as u32)),
+            Xattr::Data { data } if data.len() > size as usize => Err(Error::Sys(Errno::ERANGE)),
+            reply => Ok(reply),
+        }
     }
 
-    async fn removexattr(&self, ino: u64, name: OsString) -> Result<()> {
+    async fn removexattr(&self, ctx: RequestContext, ino: u64, name: OsString) -> Result<()> {
         trace!("removexattr");
         let path = {
             let inode_map = self.inode_map.read().await;
             inode_map.get_path(ino)?.to_owned()
         };
-        inject!(self, REMOVEXATTR, &path);
+        let _guard = inject!(self, ctx, REMOVEXATTR, &path);
+        let _mutation = self.mutations.read().await;
+        self.inject_xattr(ctx, Method::REMOVEXATTR, &path, &name)?;
+        let entry = if self.should_journal(ctx, Method::REMOVEXATTR, &path)? {
+            Some(JournalEntry::xattr(&path, &name).await?)
+        } else {
+            None
+        };
 
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let cpath = CString::new(path.as_os_str().as_bytes())?;
 
         let name = CString::new(name.as_bytes())?;
 
         let ret = spawn_blocking(move || {
-            let path_ptr = &path.as_bytes_with_nul()[0] as *const u8 as *const libc::c_char;
+            let path_ptr = &cpath.as_bytes_with_nul()[0] as *const u8 as *const libc::c_char;
             let name_ptr = &name.as_bytes_with_nul()[0] as *const u8 as *const libc::c_char;
             unsafe { lremovexattr(path_ptr, name_ptr) }
         })
@@ -1095,16 +1947,20 @@ impl AsyncFileSystemImpl for HookFs {
         if ret == -1 {
             return Err(Error::last());
         }
+        if let Some(entry) = entry {
+            self.journal.lock().unwrap().push(entry);
+        }
+        inject_post!(self, ctx, REMOVEXATTR, &path);
         Ok(())
     }
 
-    async fn access(&self, ino: u64, mask: i32) -> Result<()> {
+    async fn access(&self, ctx: RequestContext, ino: u64, mask: i32) -> Result<()> {
         trace!("access");
         let path = {
             let inode_map = self.inode_map.read().await;
             inode_map.get_path(ino)?.to_owned()
         };
-        inject!(self, ACCESS, &path);
+        let _guard = inject!(self, ctx, ACCESS, &path);
 
         let mask = AccessFlags::from_bits_truncate(mask as i32);
 
@@ -1117,6 +1973,7 @@ impl AsyncFileSystemImpl for HookFs {
 
     async fn create(
         &self,
+        ctx: RequestContext,
         parent: u64,
         name: OsString,
         mode: u32,
@@ -1133,7 +1990,9 @@ impl AsyncFileSystemImpl for HookFs {
             let parent_path = inode_map.get_path(parent)?;
             parent_path.join(name)
         };
-        inject!(self, CREATE, path.as_path());
+        let ctx = ctx.with_flags(flags);
+        let _guard = inject!(self, ctx, CREATE, path.as_path());
+        let _mutation = self.mutations.read().await;
 
         let filtered_flags = flags & (!libc::O_APPEND);
         let filtered_flags = OFlag::from_bits_truncate(filtered_flags as i32);
@@ -1143,10 +2002,16 @@ impl AsyncFileSystemImpl for HookFs {
         trace!("create with flags: {:?}, mode: {:?}", filtered_flags, mode);
 
         let fd = async_open(&path, filtered_flags, mode).await?;
+        self.journal(
+            ctx,
+            Method::CREATE,
+            JournalEntry::Create { path: path.clone() },
+        )?;
+        self.hide_entry(ctx, Method::CREATE, &path)?;
         trace!("setting owner {}:{} for file", uid, gid);
         fchown(fd, Some(Uid::from_raw(uid)), Some(Gid::from_raw(gid)))?;
 
-        let stat = self.get_file_attr(&path).await?;
+        let stat = self.get_file_attr(ctx, Method::CREATE, &path).await?;
 
         trace!("insert ({}, {}) into inode_map", stat.ino, path.display());
         self.inode_map
@@ -1160,7 +2025,20 @@ impl AsyncFileSystemImpl for HookFs {
             .opened_files
             .write()
             .await
-            .insert(File::new(file, &path));
+            .insert(File::new(file, &path, flags));
+
+        // the file is created, but the caller will not receive the fh if the
+        // post injection fails, so it has to be closed here
+        if self.enable_injection.load(Ordering::SeqCst) {
+            let result = self
+                .injector
+                .inject_post(&ctx, &Method::CREATE, self.rebuild_path(&path)?.as_path())
+                .await;
+            if let Err(err) = result {
+                self.opened_files.write().await.remove(fh);
+                return Err(err);
+            }
+        }
 
         // TODO: support generation number
         // this can be implemented with ioctl FS_IOC_GETVERSION
@@ -1169,13 +2047,14 @@ impl AsyncFileSystemImpl for HookFs {
         let finish_time = std::time::Instant::now();
         let mut reply = Create::new(finish_time - start_time, stat, 0, fh as u64, flags);
         trace!("before inject {:?}", reply);
-        inject_reply!(self, CREATE, path.as_path(), reply, Create);
+        inject_reply!(self, ctx, CREATE, path.as_path(), reply, Create);
         trace!("after inject {:?}", reply);
         Ok(reply)
     }
 
     async fn getlk(
         &self,
+        _ctx: RequestContext,
         _ino: u64,
         _fh: u64,
         _lock_owner: u64,
@@ -1191,6 +2070,7 @@ impl AsyncFileSystemImpl for HookFs {
 
     async fn setlk(
         &self,
+        _ctx: RequestContext,
         _ino: u64,
         _fh: u64,
         _lock_owner: u64,
@@ -1204,7 +2084,14 @@ impl AsyncFileSystemImpl for HookFs {
         Err(Error::Sys(Errno::ENOSYS))
     }
 
-    async fn bmap(&self, _ino: u64, _blocksize: u32, _idx: u64, reply: ReplyBmap) {
+    async fn bmap(
+        &self,
+        _ctx: RequestContext,
+        _ino: u64,
+        _blocksize: u32,
+        _idx: u64,
+        reply: ReplyBmap,
+    ) {
         error!("unimplemented");
         reply.error(nix::libc::ENOSYS);
     }
@@ -1216,6 +2103,53 @@ async fn async_stat(path: &Path) -> Result<stat::FileStat> {
     Ok(spawn_blocking(move || stat::lstat(&path_clone)).await??)
 }
 
+async fn async_fstat(fd: RawFd) -> Result<stat::FileStat> {
+    trace!("async read stat from fd {}", fd);
+    Ok(spawn_blocking(move || stat::fstat(fd)).await??)
+}
+
+// async_pread reads until the size is reached or the end of the file
+async fn async_pread(fd: RawFd, offset: i64, size: usize) -> Result<Vec<u8>> {
+    trace!("async read {} bytes at {} from fd {}", size, offset, fd);
+    Ok(spawn_blocking(move || -> nix::Result<Vec<u8>> {
+        let mut buf = vec![0u8; size];
+        let mut read = 0;
+        while read < size {
+            match pread(fd, &mut buf[read..], offset + read as i64) {
+                Ok(0) => break,
+                Ok(len) => read += len,
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        buf.truncate(read);
+        Ok(buf)
+    })
+    .await??)
+}
+
+async fn async_pwrite(fd: RawFd, offset: i64, data: Vec<u8>) -> Result<()> {
+    trace!(
+        "async write {} bytes at {} to fd {}",
+        data.len(),
+        offset,
+        fd
+    );
+    spawn_blocking(move || -> nix::Result<()> {
+        let mut written = 0;
+        while written < data.len() {
+            match pwrite(fd, &data[written..], offset + written as i64) {
+                Ok(len) => written += len,
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    })
+    .await??;
+    Ok(())
+}
+
 async fn async_chown(path: &Path, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
     let path_clone = path.to_path_buf();
     spawn_blocking(move || chown(&path_clone, uid.map(Uid::from_raw), gid.map(Gid::from_raw)))
@@ -1275,6 +2209,13 @@ async fn async_unlink(path: &Path) -> Result<()> {
     Ok(())
 }
 
+async fn async_renameat(path: &Path, new_path: &Path) -> Result<()> {
+    let path_clone = path.to_path_buf();
+    let new_path_clone = new_path.to_path_buf();
+    spawn_blocking(move || renameat(None, &path_clone, None, &new_path_clone)).await??;
+    Ok(())
+}
+
 async fn async_rmdir(path: CString) -> Result<i32> {
     let ret = spawn_blocking(move || {
         let path_ptr = path.as_bytes_with_nul()[0] as *const u8 as *mut i8;
@@ -1,9 +1,12 @@
 use fuser::*;
 use log::{debug, error, trace};
+use nix::errno::Errno;
 
 use super::errors::Result;
 
+use std::ffi::OsString;
 use std::fmt::Debug;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum Reply<'a> {
@@ -16,6 +19,9 @@ pub enum Reply<'a> {
     Create(&'a mut Create),
     _Lock(&'a mut Lock),
     Xattr(&'a mut Xattr),
+    DirEntries(&'a mut DirEntries),
+    // the target of a symlink, returned by readlink
+    Link(&'a mut Data),
 }
 
 #[derive(Debug)]
@@ -111,6 +117,55 @@ impl Write {
     }
 }
 
+// WriteData describes the chunks which will actually be persisted by a write
+// request. The reply to the caller is not affected by it.
+#[derive(Debug)]
+pub struct WriteData {
+    pub chunks: Vec<(i64, Vec<u8>)>,
+}
+impl WriteData {
+    pub fn new(offset: i64, data: Vec<u8>) -> Self {
+        Self {
+            chunks: vec![(offset, data)],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub ino: u64,
+    pub kind: FileType,
+    pub name: OsString,
+}
+impl DirEntry {
+    pub fn new(ino: u64, kind: FileType, name: OsString) -> Self {
+        Self { ino, kind, name }
+    }
+}
+
+// DirEntries is the whole listing of a directory. An error in the listing
+// fails the readdir request which reaches it.
+#[derive(Debug)]
+pub struct DirEntries {
+    pub entries: Vec<std::result::Result<DirEntry, Errno>>,
+}
+impl DirEntries {
+    pub fn new(entries: Vec<std::result::Result<DirEntry, Errno>>) -> Self {
+        Self { entries }
+    }
+}
+
+// Rename describes how a rename request is performed. A non-atomic rename
+// exposes an intermediate state during the window.
+#[derive(Debug, Clone, Copy)]
+pub enum Rename {
+    Atomic,
+    // the destination is removed before the source is renamed
+    RemoveFirst(Duration),
+    // the source is linked to the destination before it's removed
+    LinkFirst(Duration),
+}
+
 #[derive(Debug)]
 pub struct Create {
     pub ttl: std::time::Duration,
@@ -42,3 +42,12 @@ where
     }
     unreachable!()
 }
+
+// block_on runs the future on the runtime, and blocks the current thread,
+// which should not be a thread of the runtime, until it completes
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    if let Some(runtime) = &*RUNTIME.read().unwrap() {
+        return runtime.handle().block_on(future);
+    }
+    unreachable!()
+}
@@ -0,0 +1,149 @@
+use fuser::FileAttr;
+use log::trace;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Snapshot is the state of a file before the changes which are not visible
+// yet. Only the original content of the changed ranges is kept, and the
+// others are read from the file.
+#[derive(Debug)]
+struct Snapshot {
+    attr: FileAttr,
+    ranges: Vec<(u64, Vec<u8>)>,
+    until: Instant,
+}
+
+impl Snapshot {
+    // missing returns the parts of [start, end) inside the original file,
+    // whose original content has not been saved
+    fn missing(&self, start: u64, end: u64) -> Vec<(u64, u64)> {
+        let mut missing = vec![(start, end.min(self.attr.size))];
+        for (saved_start, data) in self.ranges.iter() {
+            let saved_end = saved_start + data.len() as u64;
+            missing = missing
+                .into_iter()
+                .flat_map(|(start, end)| {
+                    vec![(start, end.min(*saved_start)), (start.max(saved_end), end)]
+                })
+                .filter(|(start, end)| start < end)
+                .collect();
+        }
+        missing
+    }
+}
+
+// Snapshots keeps the old state of the recently changed files, and the
+// recently created entries, until their windows pass
+#[derive(Debug, Default)]
+pub struct Snapshots {
+    files: HashMap<PathBuf, Snapshot>,
+    entries: HashMap<PathBuf, Instant>,
+}
+
+impl Snapshots {
+    fn get(&mut self, path: &Path) -> Option<&mut Snapshot> {
+        let now = Instant::now();
+        if let Some(snapshot) = self.files.get(path) {
+            if snapshot.until <= now {
+                trace!("snapshot of {} expired", path.display());
+                self.files.remove(path);
+            }
+        }
+        self.files.get_mut(path)
+    }
+
+    // purge forgets the expired snapshots and entries
+    fn purge(&mut self) {
+        let now = Instant::now();
+        self.files.retain(|_, snapshot| snapshot.until > now);
+        self.entries.retain(|_, until| *until > now);
+    }
+
+    // contains tells whether the file has a snapshot. The window of a
+    // snapshot is measured from the first change, and is not extended by the
+    // later ones, so that a file under continuous changes becomes visible
+    pub fn contains(&mut self, path: &Path) -> bool {
+        self.get(path).is_some()
+    }
+
+    pub fn insert(&mut self, path: PathBuf, attr: FileAttr, window: Duration) {
+        trace!("snapshot {} for {:?}", path.display(), window);
+        self.purge();
+        self.files.entry(path).or_insert_with(|| Snapshot {
+            attr,
+            ranges: Vec::new(),
+            until: Instant::now() + window,
+        });
+    }
+
+    // missing returns the parts of [start, end) which should be saved before
+    // they are changed
+    pub fn missing(&mut self, path: &Path, start: u64, end: u64) -> Vec<(u64, u64)> {
+        self.get(path)
+            .map(|snapshot| snapshot.missing(start, end))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&mut self, path: &Path, offset: u64, data: Vec<u8>) {
+        if let Some(snapshot) = self.get(path) {
+            trace!(
+                "save {} bytes at {} of {}",
+                data.len(),
+                offset,
+                path.display()
+            );
+            snapshot.ranges.push((offset, data));
+        }
+    }
+
+    // read returns the original content of [offset, offset + size), with the
+    // unchanged parts taken from the current content
+    pub fn read(
+        &mut self,
+        path: &Path,
+        offset: u64,
+        size: usize,
+        current: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.get(path).map(|snapshot| {
+            let end = std::cmp::min(offset.saturating_add(size as u64), snapshot.attr.size);
+            let len = end.saturating_sub(offset) as usize;
+
+            let mut data = current[..std::cmp::min(len, current.len())].to_owned();
+            data.resize(len, 0);
+            for (saved_start, saved) in snapshot.ranges.iter() {
+                let start = std::cmp::max(*saved_start, offset);
+                let stop = std::cmp::min(saved_start + saved.len() as u64, end);
+                if start < stop {
+                    data[(start - offset) as usize..(stop - offset) as usize].copy_from_slice(
+                        &saved[(start - saved_start) as usize..(stop - saved_start) as usize],
+                    );
+                }
+            }
+            data
+        })
+    }
+
+    pub fn attr(&mut self, path: &Path) -> Option<FileAttr> {
+        self.get(path).map(|snapshot| snapshot.attr)
+    }
+
+    pub fn hide(&mut self, path: PathBuf, window: Duration) {
+        trace!("hide {} for {:?}", path.display(), window);
+        self.purge();
+        self.entries.insert(path, Instant::now() + window);
+    }
+
+    pub fn is_hidden(&mut self, path: &Path) -> bool {
+        match self.entries.get(path) {
+            Some(until) if *until > Instant::now() => true,
+            Some(_) => {
+                self.entries.remove(path);
+                false
+            }
+            None => false,
+        }
+    }
+}
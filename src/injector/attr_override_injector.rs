@@ -1,14 +1,15 @@
 use super::filter;
 use super::Injector;
 
-use super::injector_config::{AttrOverrideConfig, FileType as ConfigFileType, FilterConfig};
-use crate::hookfs::Result;
+use super::injector_config::{AttrOverrideConfig, FileType as ConfigFileType, FilterConfig, Skew};
+use crate::hookfs::{RequestContext, Result};
 
 use async_trait::async_trait;
 use fuser::{FileAttr, FileType};
 use log::{debug, trace};
 
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
 pub struct AttrOverrideInjector {
@@ -26,17 +27,23 @@ pub struct AttrOverrideInjector {
     uid: Option<u32>,
     gid: Option<u32>,
     rdev: Option<u32>,
+
+    atime_skew: Option<Skew>,
+    mtime_skew: Option<Skew>,
+    ctime_skew: Option<Skew>,
+    size_delta: Option<i64>,
+    perm_mask: Option<u16>,
 }
 
 #[async_trait]
 impl Injector for AttrOverrideInjector {
-    async fn inject(&self, _: &filter::Method, _: &Path) -> Result<()> {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
         Ok(())
     }
 
-    fn inject_attr(&self, attr: &mut FileAttr, path: &Path) {
+    fn inject_attr(&self, ctx: &RequestContext, attr: &mut FileAttr, path: &Path) {
         // AttrOverrideInjector should always pass method filter
-        if !self.filter.filter(&filter::Method::LOOKUP, path) {
+        if !self.filter.filter(ctx, &filter::Method::LOOKUP, path) {
             return;
         }
 
@@ -88,6 +95,60 @@ impl Injector for AttrOverrideInjector {
             trace!("overriding rdev");
             attr.rdev = rdev
         }
+
+        if let Some(skew) = self.atime_skew {
+            trace!("skewing atime");
+            attr.atime = apply_skew(attr.atime, skew)
+        }
+        if let Some(skew) = self.mtime_skew {
+            trace!("skewing mtime");
+            attr.mtime = apply_skew(attr.mtime, skew)
+        }
+        if let Some(skew) = self.ctime_skew {
+            trace!("skewing ctime");
+            attr.ctime = apply_skew(attr.ctime, skew)
+        }
+        if let Some(delta) = self.size_delta {
+            trace!("adjusting size");
+            attr.size = if delta < 0 {
+                attr.size.saturating_sub(delta.abs() as u64)
+            } else {
+                attr.size.saturating_add(delta as u64)
+            }
+        }
+        if let Some(mask) = self.perm_mask {
+            trace!("masking perm");
+            attr.perm &= !mask
+        }
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+// the skewed time is clamped to the range which can be represented by the
+// kernel, instead of overflowing
+fn apply_skew(time: SystemTime, skew: Skew) -> SystemTime {
+    let latest = UNIX_EPOCH + Duration::from_secs(i64::MAX as u64);
+    if skew.negative {
+        time.checked_sub(skew.duration)
+            .map_or(UNIX_EPOCH, |time| time.max(UNIX_EPOCH))
+    } else {
+        time.checked_add(skew.duration)
+            .map_or(latest, |time| time.min(latest))
+    }
+}
+
+pub fn convert_file_type(kind: ConfigFileType) -> FileType {
+    match kind {
+        ConfigFileType::Directory => FileType::Directory,
+        ConfigFileType::NamedPipe => FileType::NamedPipe,
+        ConfigFileType::RegularFile => FileType::RegularFile,
+        ConfigFileType::Socket => FileType::Socket,
+        ConfigFileType::Symlink => FileType::Symlink,
+        ConfigFileType::CharDevice => FileType::CharDevice,
+        ConfigFileType::BlockDevice => FileType::BlockDevice,
     }
 }
 
@@ -99,21 +160,14 @@ impl AttrOverrideInjector {
             path: Some(conf.path),
             methods: None,
             percent: conf.percent,
+            ..Default::default()
         })?;
 
         let atime = conf.atime;
         let mtime = conf.mtime;
         let ctime = conf.ctime;
 
-        let kind = conf.kind.map(|item| match item {
-            ConfigFileType::Directory => FileType::Directory,
-            ConfigFileType::NamedPipe => FileType::NamedPipe,
-            ConfigFileType::RegularFile => FileType::RegularFile,
-            ConfigFileType::Socket => FileType::Socket,
-            ConfigFileType::Symlink => FileType::Symlink,
-            ConfigFileType::CharDevice => FileType::CharDevice,
-            ConfigFileType::BlockDevice => FileType::BlockDevice,
-        });
+        let kind = conf.kind.map(convert_file_type);
 
         Ok(Self {
             filter,
@@ -130,6 +184,12 @@ impl AttrOverrideInjector {
             uid: conf.uid,
             gid: conf.gid,
             rdev: conf.rdev,
+
+            atime_skew: conf.atime_skew,
+            mtime_skew: conf.mtime_skew,
+            ctime_skew: conf.ctime_skew,
+            size_delta: conf.size_delta,
+            perm_mask: conf.perm_mask,
         })
     }
 }
@@ -0,0 +1,159 @@
+use super::filter;
+use super::Injector;
+
+use super::injector_config::BadBlockConfig;
+use crate::hookfs::{Error, RequestContext, Result};
+
+use async_trait::async_trait;
+use log::{debug, trace};
+use nix::errno::Errno;
+use rand::Rng;
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// the decided blocks of a file are forgotten once there are too many of them,
+// and the good ones among them are decided again when they are read
+const MAX_DECIDED_BLOCKS: usize = 1 << 16;
+
+#[derive(Debug, Default)]
+struct BadBlocks {
+    // bad ranges in [start, end) form
+    ranges: Vec<(u64, u64)>,
+    // blocks whose state has been decided by the density
+    decided: HashSet<u64>,
+}
+
+impl BadBlocks {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.ranges
+            .iter()
+            .any(|(bad_start, bad_end)| *bad_start < end && start < *bad_end)
+    }
+
+    fn remove(&mut self, start: u64, end: u64) {
+        self.ranges = self
+            .ranges
+            .iter()
+            .flat_map(|&(bad_start, bad_end)| {
+                vec![
+                    (bad_start, bad_end.min(start)),
+                    (bad_start.max(end), bad_end),
+                ]
+            })
+            .filter(|(bad_start, bad_end)| bad_start < bad_end)
+            .collect();
+    }
+}
+
+#[derive(Debug)]
+pub struct BadBlockInjector {
+    filter: filter::Filter,
+
+    ranges: Vec<(u64, u64)>,
+    density: f64,
+    block_size: u64,
+    remap: bool,
+    errno: Errno,
+
+    files: Mutex<HashMap<PathBuf, BadBlocks>>,
+}
+
+#[async_trait]
+impl Injector for BadBlockInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    async fn inject_io(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        offset: i64,
+        size: usize,
+    ) -> Result<()> {
+        let is_read = *method == filter::Method::READ;
+        let is_write = *method == filter::Method::WRITE;
+        if size == 0 || !(is_read || is_write) || !self.filter.matches_path(path) {
+            return Ok(());
+        }
+
+        let start = offset as u64;
+        let end = start + size as u64;
+
+        // the bad blocks are the state of the file, so the percent and the
+        // other selectors only apply to the decision of new bad blocks
+        let decide = is_read && self.density > 0f64 && self.filter.filter(ctx, method, path);
+
+        let mut files = self.files.lock().unwrap();
+        let blocks = files.entry(path.to_owned()).or_insert_with(|| BadBlocks {
+            ranges: self.ranges.clone(),
+            decided: HashSet::new(),
+        });
+
+        if decide {
+            let mut rng = rand::thread_rng();
+            for block in (start / self.block_size)..=((end - 1) / self.block_size) {
+                if blocks.decided.len() >= MAX_DECIDED_BLOCKS {
+                    trace!("forget decided blocks of {}", path.display());
+                    blocks.decided.clear();
+                }
+                let block_start = block * self.block_size;
+                let block_end = block_start + self.block_size;
+                if blocks.decided.insert(block)
+                    && !blocks.overlaps(block_start, block_end)
+                    && rng.gen::<f64>() < self.density
+                {
+                    trace!("block {} of {} goes bad", block, path.display());
+                    blocks.ranges.push((block_start, block_end));
+                }
+            }
+        }
+
+        if is_write {
+            // the whole blocks touched by the write are remapped
+            if self.remap {
+                let start = start / self.block_size * self.block_size;
+                let end = (end - 1) / self.block_size * self.block_size + self.block_size;
+                trace!("remap [{}, {}) of {}", start, end, path.display());
+                blocks.remove(start, end);
+            }
+            return Ok(());
+        }
+
+        if self.filter.matches_method(method) && blocks.overlaps(start, end) {
+            debug!("read [{}, {}) overlaps bad blocks", start, end);
+            return Err(Error::Sys(self.errno));
+        }
+
+        Ok(())
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl BadBlockInjector {
+    pub fn build(conf: BadBlockConfig) -> anyhow::Result<Self> {
+        trace!("build bad block injector");
+
+        let ranges = conf
+            .ranges
+            .iter()
+            .map(|range| (range.offset, range.offset + range.length))
+            .collect();
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            ranges,
+            density: conf.density.unwrap_or(0f64),
+            block_size: conf.block_size.filter(|size| *size > 0).unwrap_or(512),
+            remap: conf.remap,
+            errno: Errno::from_i32(conf.errno.unwrap_or(libc::EIO)),
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+}
@@ -0,0 +1,194 @@
+use async_trait::async_trait;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::filter;
+use super::injector_config::BandwidthConfig;
+use super::Injector;
+use crate::hookfs::{RequestContext, Result};
+
+use glob::Pattern;
+use log::{debug, trace};
+use tokio::time::delay_for;
+
+// TokenBucket allows `capacity` bytes to pass at once and refills with `rate`
+// bytes per second. A request larger than the remaining tokens goes into debt,
+// and the following requests wait until the debt is paid off.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, capacity: u64) -> TokenBucket {
+        TokenBucket {
+            rate: rate as f64,
+            capacity: capacity as f64,
+            state: Mutex::new((capacity as f64, Instant::now())),
+        }
+    }
+
+    // take consumes `size` tokens and returns how long the caller should wait
+    fn take(&self, size: usize) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last) = *state;
+
+        let now = Instant::now();
+        let refilled = (now - last).as_secs_f64() * self.rate;
+        let tokens = (tokens + refilled).min(self.capacity) - size as f64;
+        *state = (tokens, now);
+
+        if tokens >= 0f64 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-tokens / self.rate)
+        }
+    }
+
+    // idle means the bucket has been refilled, so it's the same as a new one
+    fn idle(&self, now: Instant) -> bool {
+        let (tokens, last) = *self.state.lock().unwrap();
+        tokens + (now - last).as_secs_f64() * self.rate >= self.capacity
+    }
+}
+
+#[derive(Debug)]
+struct Limit {
+    rate: u64,
+    capacity: u64,
+    per_file: bool,
+
+    groups: Vec<(Pattern, TokenBucket)>,
+    shared: TokenBucket,
+    files: Mutex<HashMap<PathBuf, TokenBucket>>,
+}
+
+impl Limit {
+    fn new(rate: u64, capacity: u64, per_file: bool, groups: &[Pattern]) -> Limit {
+        Limit {
+            rate,
+            capacity,
+            per_file,
+            groups: groups
+                .iter()
+                .map(|group| (group.clone(), TokenBucket::new(rate, capacity)))
+                .collect(),
+            shared: TokenBucket::new(rate, capacity),
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn take(&self, path: &Path, size: usize) -> Duration {
+        let group = self
+            .groups
+            .iter()
+            .find(|(group, _)| group.matches_path_with(path, filter::MATCH_OPTIONS));
+        if let Some((_, bucket)) = group {
+            return bucket.take(size);
+        }
+        if !self.per_file {
+            return self.shared.take(size);
+        }
+
+        let mut files = self.files.lock().unwrap();
+        if !files.contains_key(path) {
+            // the idle buckets are dropped, as they can be created again
+            let now = Instant::now();
+            files.retain(|_, bucket| !bucket.idle(now));
+        }
+        files
+            .entry(path.to_owned())
+            .or_insert_with(|| TokenBucket::new(self.rate, self.capacity))
+            .take(size)
+    }
+}
+
+#[derive(Debug)]
+pub struct BandwidthInjector {
+    filter: filter::Filter,
+
+    read: Option<Limit>,
+    write: Option<Limit>,
+
+    latency: Duration,
+    byte_latency: Duration,
+    entry_latency: Duration,
+}
+
+#[async_trait]
+impl Injector for BandwidthInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    async fn inject_io(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        _offset: i64,
+        size: usize,
+    ) -> Result<()> {
+        trace!("test for filter");
+        if !self.filter.filter(ctx, method, path) {
+            return Ok(());
+        }
+
+        let (limit, unit_latency) = match *method {
+            filter::Method::READ => (self.read.as_ref(), self.byte_latency),
+            filter::Method::WRITE => (self.write.as_ref(), self.byte_latency),
+            filter::Method::READDIR => (None, self.entry_latency),
+            _ => return Ok(()),
+        };
+
+        let mut delay = self.latency + unit_latency * size as u32;
+        if let Some(limit) = limit {
+            delay += limit.take(path, size);
+        }
+
+        if delay > Duration::from_secs(0) {
+            debug!("inject io delay {:?} for {} units", delay, size);
+            delay_for(delay).await;
+            debug!("latency finished");
+        }
+
+        Ok(())
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl BandwidthInjector {
+    pub fn build(conf: BandwidthConfig) -> anyhow::Result<Self> {
+        trace!("build bandwidth injector");
+
+        let per_file = conf.per_file;
+        let burst = conf.burst;
+        let groups = conf
+            .groups
+            .iter()
+            .map(|group| Pattern::new(group))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let limit = |rate: Option<u64>| {
+            rate.filter(|rate| *rate > 0)
+                .map(|rate| Limit::new(rate, burst.unwrap_or(rate), per_file, &groups))
+        };
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            read: limit(conf.read_bps),
+            write: limit(conf.write_bps),
+            latency: conf.latency.unwrap_or_default(),
+            byte_latency: conf.byte_latency.unwrap_or_default(),
+            entry_latency: conf.entry_latency.unwrap_or_default(),
+        })
+    }
+}
@@ -0,0 +1,158 @@
+use async_trait::async_trait;
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use super::filter;
+use super::injector_config::ConcurrencyConfig;
+use super::{Guard, Injector};
+use crate::hookfs::runtime::spawn;
+use crate::hookfs::{Error, RequestContext, Result};
+
+use anyhow::anyhow;
+use log::{debug, info, trace};
+use nix::errno::Errno;
+use tokio::sync::Semaphore;
+use tokio::time::delay_for;
+
+// the counts are logged at this interval, if they have changed
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+// Stats counts the operations which have waited for a slot, and the ones
+// failed because of a full queue
+#[derive(Debug, Default)]
+struct Stats {
+    queued: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl Stats {
+    fn log(&self) {
+        info!(
+            "concurrency injector: {} operations queued, {} rejected",
+            self.queued.load(Ordering::SeqCst),
+            self.rejected.load(Ordering::SeqCst)
+        );
+    }
+}
+
+#[derive(Debug)]
+pub struct ConcurrencyInjector {
+    filter: filter::Filter,
+
+    slots: Arc<Semaphore>,
+    queue: Option<usize>,
+    errno: Errno,
+    service_time: Duration,
+
+    waiting: AtomicUsize,
+    stats: Arc<Stats>,
+}
+
+#[async_trait]
+impl Injector for ConcurrencyInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    // the slot is held until the operation completes
+    async fn admit(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<Option<Guard>> {
+        trace!("test for filter");
+        if !self.filter.filter(ctx, method, path) {
+            return Ok(None);
+        }
+
+        let permit = match self.slots.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let waiting = self.waiting.fetch_add(1, Ordering::SeqCst);
+                if self.queue.map_or(false, |queue| waiting >= queue) {
+                    self.waiting.fetch_sub(1, Ordering::SeqCst);
+                    let rejected = self.stats.rejected.fetch_add(1, Ordering::SeqCst) + 1;
+                    debug!("queue is full, reject operation ({} rejected)", rejected);
+                    return Err(Error::Sys(self.errno));
+                }
+
+                let queued = self.stats.queued.fetch_add(1, Ordering::SeqCst) + 1;
+                debug!("wait for a free slot ({} queued)", queued);
+                let permit = self.slots.clone().acquire_owned().await;
+                self.waiting.fetch_sub(1, Ordering::SeqCst);
+                permit
+            }
+        };
+
+        delay_for(self.service_time).await;
+
+        Ok(Some(box permit))
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl ConcurrencyInjector {
+    pub fn build(conf: ConcurrencyConfig) -> anyhow::Result<Self> {
+        trace!("build concurrency injector");
+
+        if conf.depth == 0 {
+            return Err(anyhow!("depth should be greater than 0"));
+        }
+
+        let service_time = match (conf.service_time, conf.iops) {
+            (Some(service_time), _) => service_time,
+            (None, Some(iops)) if iops > 0 => {
+                Duration::from_secs_f64(conf.depth as f64 / iops as f64)
+            }
+            _ => Duration::from_secs(0),
+        };
+
+        let stats = Arc::new(Stats::default());
+        let weak_stats = Arc::downgrade(&stats);
+        spawn(async move { log_stats(weak_stats).await });
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            slots: Arc::new(Semaphore::new(conf.depth)),
+            queue: conf.queue,
+            errno: Errno::from_i32(conf.errno.unwrap_or(libc::EAGAIN)),
+            service_time,
+            waiting: AtomicUsize::new(0),
+            stats,
+        })
+    }
+}
+
+impl Drop for ConcurrencyInjector {
+    fn drop(&mut self) {
+        self.stats.log();
+    }
+}
+
+// log_stats logs the changed counts periodically, until the injector is
+// dropped
+async fn log_stats(stats: Weak<Stats>) {
+    let mut logged = (0, 0);
+    loop {
+        delay_for(STATS_INTERVAL).await;
+        let stats = match stats.upgrade() {
+            Some(stats) => stats,
+            None => return,
+        };
+        let counts = (
+            stats.queued.load(Ordering::SeqCst),
+            stats.rejected.load(Ordering::SeqCst),
+        );
+        if counts != logged {
+            stats.log();
+            logged = counts;
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use super::filter;
+use super::injector_config::ConsistencyConfig;
+use super::Injector;
+use crate::hookfs::{RequestContext, Result};
+
+use async_trait::async_trait;
+use log::{debug, trace};
+
+use std::path::Path;
+use std::time::Duration;
+
+// ConsistencyInjector only selects the changes to delay. The old state is
+// kept and served by the HookFs. A path is decided once for the window, so
+// that the changes to it are delayed together.
+#[derive(Debug)]
+pub struct ConsistencyInjector {
+    filter: filter::Filter,
+    decisions: filter::Decisions,
+
+    window: Duration,
+}
+
+#[async_trait]
+impl Injector for ConsistencyInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn lag(&self, ctx: &RequestContext, method: &filter::Method, path: &Path) -> Option<Duration> {
+        // the other methods don't take part in the decision
+        if !self.filter.matches_method(method) {
+            return None;
+        }
+
+        if self
+            .decisions
+            .decide(ctx, path, || self.filter.filter(ctx, method, path))
+        {
+            debug!("delay the visibility of {}", path.display());
+            return Some(self.window);
+        }
+
+        None
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl ConsistencyInjector {
+    pub fn build(conf: ConsistencyConfig) -> anyhow::Result<Self> {
+        trace!("build consistency injector");
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            decisions: filter::Decisions::new(conf.window),
+            window: conf.window,
+        })
+    }
+}
@@ -0,0 +1,106 @@
+use super::filter;
+use super::Injector;
+
+use super::injector_config::{Content, ContentConfig};
+use crate::hookfs::{RequestContext, Result};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use fuser::{FileAttr, FileType};
+use log::{debug, trace};
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+// the decision for a path is kept for a while, so that the size and the
+// content of a file agree
+const DECISION_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct ContentInjector {
+    filter: filter::Filter,
+    decisions: filter::Decisions,
+
+    content: Arc<Vec<u8>>,
+}
+
+#[async_trait]
+impl Injector for ContentInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    // ContentInjector should always pass method filter, so that the size is
+    // consistent with the content
+    fn content(
+        &self,
+        ctx: &RequestContext,
+        _: &filter::Method,
+        path: &Path,
+    ) -> Option<Arc<Vec<u8>>> {
+        let matched = || self.filter.filter(ctx, &filter::Method::READ, path);
+        if self.decisions.decide(ctx, path, matched) {
+            debug!("override content of {}", path.display());
+            return Some(self.content.clone());
+        }
+
+        None
+    }
+
+    fn inject_attr(&self, ctx: &RequestContext, attr: &mut FileAttr, path: &Path) {
+        if let FileType::RegularFile = attr.kind {
+            let matched = || self.filter.filter(ctx, &filter::Method::LOOKUP, path);
+            if self.decisions.decide(ctx, path, matched) {
+                trace!("overriding size");
+                attr.size = self.content.len() as u64;
+                attr.blocks = (attr.size + 511) / 512;
+            }
+        }
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl ContentInjector {
+    pub fn build(conf: ContentConfig) -> anyhow::Result<Self> {
+        trace!("build content injector");
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            decisions: filter::Decisions::new(conf.window.unwrap_or(DECISION_WINDOW)),
+            content: Arc::new(load_content(conf.content)?),
+        })
+    }
+}
+
+pub fn load_content(content: Content) -> anyhow::Result<Vec<u8>> {
+    Ok(match content {
+        Content::Inline { data } => data.into_bytes(),
+        Content::Hex { hex } => decode_hex(&hex)?,
+        Content::File { file } => std::fs::read(file)?,
+        Content::Repeat { byte, size } => vec![byte; size],
+    })
+}
+
+fn decode_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    let digits: Vec<u8> = hex
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    if digits.len() % 2 != 0 {
+        return Err(anyhow!("hex content {:?} has an odd length", hex));
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| anyhow!("hex content {:?} is invalid", hex))
+        })
+        .collect()
+}
@@ -0,0 +1,107 @@
+use super::filter;
+use super::Injector;
+
+use super::injector_config::{CorruptConfig, CorruptMode};
+use crate::hookfs::{Reply, RequestContext, Result};
+
+use async_trait::async_trait;
+use log::{debug, trace};
+use rand::{Rng, RngCore};
+
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct CorruptInjector {
+    filter: filter::Filter,
+
+    mode: CorruptMode,
+    count: usize,
+    length: Option<usize>,
+}
+
+#[async_trait]
+impl Injector for CorruptInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn inject_reply(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        reply: &mut Reply,
+    ) -> Result<()> {
+        if let Reply::Data(data) = reply {
+            if data.data.is_empty() || !self.filter.filter(ctx, method, path) {
+                return Ok(());
+            }
+
+            debug!("inject data corruption {:?}", self.mode);
+            self.corrupt(&mut data.data);
+        }
+
+        Ok(())
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl CorruptInjector {
+    pub fn build(conf: CorruptConfig) -> anyhow::Result<Self> {
+        trace!("build corrupt injector");
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            mode: conf.mode,
+            count: conf.count.unwrap_or(1),
+            length: conf.length,
+        })
+    }
+
+    fn corrupt(&self, data: &mut Vec<u8>) {
+        let mut rng = rand::thread_rng();
+
+        match self.mode {
+            CorruptMode::BitFlip => {
+                for _ in 0..self.count {
+                    let bit = rng.gen_range(0, data.len() * 8);
+                    trace!("flip bit {}", bit);
+                    data[bit / 8] ^= 1 << (bit % 8);
+                }
+            }
+            CorruptMode::Zero => {
+                let (start, end) = self.range(data.len());
+                trace!("zero range [{}, {})", start, end);
+                data[start..end].iter_mut().for_each(|byte| *byte = 0);
+            }
+            CorruptMode::Random => {
+                let (start, end) = self.range(data.len());
+                trace!("fill range [{}, {}) with random bytes", start, end);
+                rng.fill_bytes(&mut data[start..end]);
+            }
+            CorruptMode::Truncate => {
+                let len = match self.length {
+                    Some(length) => data.len().saturating_sub(length),
+                    None => rng.gen_range(0, data.len()),
+                };
+                trace!("truncate data to {}", len);
+                data.truncate(len);
+            }
+        }
+    }
+
+    // range returns a randomly placed range with the configured length. The
+    // whole buffer is returned if the length is not set.
+    fn range(&self, len: usize) -> (usize, usize) {
+        match self.length {
+            Some(length) if length < len => {
+                let start = rand::thread_rng().gen_range(0, len - length + 1);
+                (start, start + length)
+            }
+            _ => (0, len),
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use super::filter;
 use super::Injector;
 
-use super::injector_config::FaultsConfig;
-use crate::hookfs::{Error, Result};
+use super::injector_config::{FaultsConfig, Phase};
+use crate::hookfs::{Error, RequestContext, Result};
 
 use async_trait::async_trait;
 use log::{debug, trace};
@@ -18,13 +18,52 @@ pub struct FaultInjector {
     errnos: Vec<(Errno, i32)>,
 
     sum: i32,
+
+    phase: Phase,
 }
 
 #[async_trait]
 impl Injector for FaultInjector {
-    async fn inject(&self, method: &filter::Method, path: &Path) -> Result<()> {
+    async fn inject(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
+        if self.phase == Phase::Pre {
+            self.inject_fault(ctx, method, path)
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn inject_post(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
+        if self.phase == Phase::Post {
+            self.inject_fault(ctx, method, path)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl FaultInjector {
+    fn inject_fault(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
         debug!("test filter");
-        if self.filter.filter(method, path) {
+        if self.filter.filter(ctx, method, path) {
             debug!("inject io fault");
             let mut rng = rand::thread_rng();
             let attempt: f64 = rng.gen();
@@ -42,9 +81,7 @@ impl Injector for FaultInjector {
 
         Ok(())
     }
-}
 
-impl FaultInjector {
     pub fn build(conf: FaultsConfig) -> anyhow::Result<Self> {
         trace!("build fault injector");
 
@@ -59,6 +96,7 @@ impl FaultInjector {
             filter: filter::Filter::build(conf.filter)?,
             errnos,
             sum,
+            phase: conf.phase,
         })
     }
 }
@@ -1,7 +1,12 @@
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryFrom;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use super::injector_config::FilterConfig;
+use super::injector_config::{CountBy, FilterConfig};
+use super::process::ProcessInfo;
+use crate::hookfs::RequestContext;
 
 use anyhow::{anyhow, Error, Result};
 use bitflags::bitflags;
@@ -44,6 +49,21 @@ bitflags! {
         const GETLK = 1<<29;
         const SETLK = 1<<30;
         const BMAP = 1<<31;
+
+        // the methods modifying the filesystem, which fail on a read-only
+        // filesystem
+        const MUTATING = Self::SETATTR.bits
+            | Self::MKNOD.bits
+            | Self::MKDIR.bits
+            | Self::UNLINK.bits
+            | Self::RMDIR.bits
+            | Self::SYMLINK.bits
+            | Self::RENAME.bits
+            | Self::LINK.bits
+            | Self::WRITE.bits
+            | Self::SETXATTR.bits
+            | Self::REMOVEXATTR.bits
+            | Self::CREATE.bits;
     }
 }
 
@@ -82,6 +102,7 @@ impl TryFrom<&str> for Method {
             "getlk" => Ok(Method::GETLK),
             "setlk" => Ok(Method::SETLK),
             "bmap" => Ok(Method::BMAP),
+            "mutating" => Ok(Method::MUTATING),
             _ => Err(anyhow!("")),
         }
     }
@@ -92,12 +113,35 @@ impl TryFrom<&str> for Method {
 pub struct Filter {
     path_filter: Option<Pattern>,
     methods: Method,
+    pids: Option<Vec<u32>>,
+    uids: Option<Vec<u32>>,
+    gids: Option<Vec<u32>>,
+    comms: Option<Vec<String>>,
+    exe_filter: Option<Pattern>,
+    cgroup: Option<PathBuf>,
+    pid_ns: Option<u64>,
+    offset_range: Option<(u64, u64)>,
+    size_range: Option<(u64, u64)>,
+    flags: Option<OpenFlags>,
     probability: f64,
+    counter: Option<Counter>,
 }
 
+pub const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
 impl Filter {
     pub fn build(conf: FilterConfig) -> Result<Self> {
         info!("build filter");
+        let counter = Counter::build(&conf);
+        let flags = conf
+            .flags
+            .filter(|flags| !flags.is_empty())
+            .map(|flags| OpenFlags::parse(&flags))
+            .transpose()?;
         let methods = conf
             .methods
             .filter(|methods| !methods.is_empty())
@@ -109,44 +153,341 @@ impl Filter {
             })
             .unwrap_or(Method::all());
 
-        let path_filter = conf
-            .path
-            .map(|path| -> Option<Pattern> {
+        let pattern = |path: Option<String>| {
+            path.map(|path| -> Option<Pattern> {
                 if !path.is_empty() {
                     Pattern::new(&path).ok()
                 } else {
                     None
                 }
             })
-            .flatten();
+            .flatten()
+        };
+        let path_filter = pattern(conf.path);
+        let exe_filter = pattern(conf.exe);
+        let non_empty = |ids: Option<Vec<u32>>| ids.filter(|ids| !ids.is_empty());
         Ok(Self {
             path_filter,
             methods,
+            pids: non_empty(conf.pids),
+            uids: non_empty(conf.uids),
+            gids: non_empty(conf.gids),
+            comms: conf.comms.filter(|comms| !comms.is_empty()),
+            exe_filter,
+            cgroup: conf
+                .cgroup
+                .filter(|cgroup| !cgroup.is_empty())
+                .map(PathBuf::from),
+            pid_ns: conf.pid_ns,
+            offset_range: range(conf.min_offset, conf.max_offset),
+            size_range: range(conf.min_size, conf.max_size),
+            flags,
             probability: conf.percent as f64 / 100f64,
+            counter,
         })
     }
 
-    pub fn filter(&self, method: &Method, path: &Path) -> bool {
+    pub fn filter(&self, ctx: &RequestContext, method: &Method, path: &Path) -> bool {
         let mut rng = rand::thread_rng();
         let p: f64 = rng.gen();
 
-        let match_path = match &self.path_filter {
-            Some(filter) => filter.matches_path_with(
-                path,
-                MatchOptions {
-                    case_sensitive: true,
-                    require_literal_separator: true,
-                    require_literal_leading_dot: false,
-                },
-            ),
+        let match_path = self.matches_path(path);
+        let match_method = self.matches_method(method);
+        let match_request = match_id(&self.pids, ctx.pid)
+            && match_id(&self.uids, ctx.uid)
+            && match_id(&self.gids, ctx.gid);
+        let match_range = match_range(self.offset_range, ctx.offset.map(|offset| offset as u64))
+            && match_range(self.size_range, ctx.size.map(|size| size as u64));
+        let match_flags = match &self.flags {
+            Some(flags) => ctx.flags.map_or(false, |opened| flags.matches(opened)),
             None => true,
         };
-        let match_method = !(self.methods & *method).is_empty();
         let match_probability = p < self.probability;
         trace!("path filter: {}", match_path);
         trace!("method filter: {}", match_method);
+        trace!("request filter: {}", match_request);
+        trace!("range filter: {}", match_range);
+        trace!("flags filter: {}", match_flags);
         trace!("probability: {}", match_probability);
 
-        match_path && match_method && match_probability
+        // reading procfs is expensive, so it's checked after the others, and
+        // only the matched requests are counted
+        match_path
+            && match_method
+            && match_request
+            && match_range
+            && match_flags
+            && match_probability
+            && self.filter_process(ctx.pid)
+            && self.count(ctx, path)
+    }
+
+    // matches_path only checks the path, for the injectors which keep state
+    // of the matching files
+    pub fn matches_path(&self, path: &Path) -> bool {
+        match &self.path_filter {
+            Some(filter) => filter.matches_path_with(path, MATCH_OPTIONS),
+            None => true,
+        }
+    }
+
+    pub fn matches_method(&self, method: &Method) -> bool {
+        !(self.methods & *method).is_empty()
+    }
+
+    // release forgets the counts of a released handle
+    pub fn release(&self, fh: u64) {
+        if let Some(counter) = &self.counter {
+            counter.release(fh)
+        }
+    }
+
+    fn count(&self, ctx: &RequestContext, path: &Path) -> bool {
+        match &self.counter {
+            Some(counter) => counter.count(ctx, path),
+            None => true,
+        }
+    }
+
+    fn filter_process(&self, pid: u32) -> bool {
+        if self.comms.is_none()
+            && self.exe_filter.is_none()
+            && self.cgroup.is_none()
+            && self.pid_ns.is_none()
+        {
+            return true;
+        }
+
+        let process = match ProcessInfo::get(pid) {
+            Some(process) => process,
+            None => return false,
+        };
+        let match_comm = match &self.comms {
+            Some(comms) => comms.contains(&process.comm),
+            None => true,
+        };
+        let match_exe = match &self.exe_filter {
+            Some(filter) => match &process.exe {
+                Some(exe) => filter.matches_path_with(exe, MATCH_OPTIONS),
+                None => false,
+            },
+            None => true,
+        };
+        let match_cgroup = match &self.cgroup {
+            Some(prefix) => process
+                .cgroups
+                .iter()
+                .any(|cgroup| Path::new(cgroup).starts_with(prefix)),
+            None => true,
+        };
+        let match_pid_ns = match self.pid_ns {
+            Some(pid_ns) => process.pid_ns == Some(pid_ns),
+            None => true,
+        };
+        trace!("process filter: {:?}", process);
+
+        match_comm && match_exe && match_cgroup && match_pid_ns
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq)]
+enum CountKey {
+    All,
+    Path(PathBuf),
+    Handle(u64),
+}
+
+// the decisions of the recent requests are kept, as a request can be
+// filtered several times by an injector
+const RECENT_REQUESTS: usize = 64;
+
+#[derive(Debug, Default)]
+struct Count {
+    matched: u64,
+    injected: u64,
+    recent: VecDeque<(u64, bool)>,
+}
+
+#[derive(Debug)]
+struct Counter {
+    skip: u64,
+    every: u64,
+    limit: Option<u64>,
+    count_by: CountBy,
+    counts: Mutex<HashMap<CountKey, Count>>,
+}
+
+impl Counter {
+    fn build(conf: &FilterConfig) -> Option<Self> {
+        let limit = if conf.once {
+            Some(conf.limit.unwrap_or(1).min(1))
+        } else {
+            conf.limit
+        };
+        if conf.skip.is_none() && conf.every.is_none() && limit.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            skip: conf.skip.unwrap_or(0),
+            every: conf.every.unwrap_or(1).max(1),
+            limit,
+            count_by: conf.count_by,
+            counts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn count(&self, ctx: &RequestContext, path: &Path) -> bool {
+        let key = match self.count_by {
+            CountBy::All => CountKey::All,
+            CountBy::Path => CountKey::Path(path.to_owned()),
+            // the requests without a file handle are counted together
+            CountBy::Handle => ctx.fh.map(CountKey::Handle).unwrap_or(CountKey::All),
+        };
+
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(key).or_default();
+        if let Some((_, decision)) = count
+            .recent
+            .iter()
+            .find(|(unique, _)| *unique == ctx.unique)
+        {
+            return *decision;
+        }
+
+        let decision = self.decide(count);
+        if count.recent.len() >= RECENT_REQUESTS {
+            count.recent.pop_front();
+        }
+        count.recent.push_back((ctx.unique, decision));
+        decision
+    }
+
+    fn decide(&self, count: &mut Count) -> bool {
+        count.matched += 1;
+        trace!("count filter: {:?}", count);
+
+        if count.matched <= self.skip || (count.matched - self.skip - 1) % self.every != 0 {
+            return false;
+        }
+        if let Some(limit) = self.limit {
+            if count.injected >= limit {
+                return false;
+            }
+        }
+        count.injected += 1;
+        true
+    }
+
+    fn release(&self, fh: u64) {
+        self.counts.lock().unwrap().remove(&CountKey::Handle(fh));
+    }
+}
+
+// Decisions keeps the decision of a filter for each path and process for a
+// while, so that the calls for the same path agree with each other. The
+// process is a part of the key, as the filter may select processes
+#[derive(Debug)]
+pub struct Decisions {
+    ttl: Duration,
+    decided: Mutex<HashMap<(PathBuf, u32), (Instant, bool)>>,
+}
+
+impl Decisions {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            decided: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn decide<F: FnOnce() -> bool>(
+        &self,
+        ctx: &RequestContext,
+        path: &Path,
+        decide: F,
+    ) -> bool {
+        let now = Instant::now();
+        let key = (path.to_owned(), ctx.pid);
+        let mut decided = self.decided.lock().unwrap();
+        if let Some((until, decision)) = decided.get(&key) {
+            if *until > now {
+                return *decision;
+            }
+        }
+
+        decided.retain(|_, (until, _)| *until > now);
+        let decision = decide();
+        trace!("decide {} for {} of {}", decision, path.display(), ctx.pid);
+        decided.insert(key, (now + self.ttl, decision));
+        decision
+    }
+}
+
+// OpenFlags are the flags which should be set on a file handle
+#[derive(Debug)]
+struct OpenFlags {
+    // O_RDONLY, O_WRONLY or O_RDWR, which are not bits
+    access_mode: Option<i32>,
+    flags: i32,
+}
+
+impl OpenFlags {
+    fn parse(names: &[String]) -> Result<Self> {
+        let mut access_mode = None;
+        let mut flags = 0;
+        for name in names {
+            let name = name.to_uppercase();
+            let name = name.strip_prefix("O_").unwrap_or(&name);
+            match name {
+                "RDONLY" => access_mode = Some(libc::O_RDONLY),
+                "WRONLY" => access_mode = Some(libc::O_WRONLY),
+                "RDWR" => access_mode = Some(libc::O_RDWR),
+                "APPEND" => flags |= libc::O_APPEND,
+                "CREAT" => flags |= libc::O_CREAT,
+                "EXCL" => flags |= libc::O_EXCL,
+                "TRUNC" => flags |= libc::O_TRUNC,
+                "SYNC" => flags |= libc::O_SYNC,
+                "DSYNC" => flags |= libc::O_DSYNC,
+                "DIRECT" => flags |= libc::O_DIRECT,
+                "NOATIME" => flags |= libc::O_NOATIME,
+                "NONBLOCK" => flags |= libc::O_NONBLOCK,
+                _ => return Err(anyhow!("unknown open flag {}", name)),
+            }
+        }
+
+        Ok(Self { access_mode, flags })
+    }
+
+    fn matches(&self, opened: i32) -> bool {
+        let match_access_mode = match self.access_mode {
+            Some(mode) => opened & libc::O_ACCMODE == mode,
+            None => true,
+        };
+
+        match_access_mode && opened & self.flags == self.flags
+    }
+}
+
+fn range(min: Option<u64>, max: Option<u64>) -> Option<(u64, u64)> {
+    if min.is_none() && max.is_none() {
+        return None;
+    }
+
+    Some((min.unwrap_or(0), max.unwrap_or(u64::MAX)))
+}
+
+fn match_range(range: Option<(u64, u64)>, value: Option<u64>) -> bool {
+    match (range, value) {
+        (Some((min, max)), Some(value)) => min <= value && value < max,
+        (Some(_), None) => false,
+        (None, _) => true,
+    }
+}
+
+fn match_id(ids: &Option<Vec<u32>>, id: u32) -> bool {
+    match ids {
+        Some(ids) => ids.contains(&id),
+        None => true,
     }
 }
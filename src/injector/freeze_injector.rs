@@ -0,0 +1,178 @@
+use async_trait::async_trait;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::filter;
+use super::injector_config::FreezeConfig;
+use super::Injector;
+use crate::hookfs::runtime::spawn;
+use crate::hookfs::{RequestContext, Result};
+
+use log::{debug, info, trace};
+use tokio::sync::Semaphore;
+use tokio::time::delay_for;
+
+const THAW_FILE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct Freeze {
+    // the gate is set while frozen, and has no permit until thawed. The
+    // semaphore is fair, so the parked operations resume in the order they
+    // arrived. Every freeze has its own gate, so a stale timeout can't thaw
+    // a later freeze.
+    gate: Mutex<Option<Arc<Semaphore>>>,
+
+    timeout: Option<Duration>,
+    refreeze: Option<Duration>,
+
+    // a disabled freeze never freezes again
+    disabled: AtomicBool,
+}
+
+impl Freeze {
+    fn gate(&self) -> Option<Arc<Semaphore>> {
+        self.gate.lock().unwrap().clone()
+    }
+
+    fn freeze(self: &Arc<Self>) {
+        let gate = {
+            let mut gate = self.gate.lock().unwrap();
+            if gate.is_some() || self.disabled.load(Ordering::SeqCst) {
+                return;
+            }
+            info!("freeze operations");
+            let new_gate = Arc::new(Semaphore::new(0));
+            *gate = Some(new_gate.clone());
+            new_gate
+        };
+
+        if let Some(timeout) = self.timeout {
+            let freeze = self.clone();
+            spawn(async move {
+                delay_for(timeout).await;
+                freeze.thaw_gate(&gate);
+            });
+        }
+    }
+
+    fn thaw(self: &Arc<Self>) {
+        if let Some(gate) = self.gate() {
+            self.thaw_gate(&gate);
+        }
+    }
+
+    // thaw_gate thaws the operations, if they are still frozen by the gate
+    fn thaw_gate(self: &Arc<Self>, gate: &Arc<Semaphore>) {
+        {
+            let mut current = self.gate.lock().unwrap();
+            match &*current {
+                Some(current_gate) if Arc::ptr_eq(current_gate, gate) => {}
+                _ => return,
+            }
+            info!("thaw frozen operations");
+            *current = None;
+            gate.add_permits(1);
+        }
+
+        if let Some(refreeze) = self.refreeze {
+            let freeze = self.clone();
+            spawn(async move {
+                delay_for(refreeze).await;
+                freeze.freeze();
+            });
+        }
+    }
+
+    fn disable(self: &Arc<Self>) {
+        self.disabled.store(true, Ordering::SeqCst);
+        self.thaw();
+    }
+
+    fn toggle(self: &Arc<Self>) {
+        match self.gate() {
+            Some(gate) => self.thaw_gate(&gate),
+            None => self.freeze(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FreezeInjector {
+    filter: filter::Filter,
+
+    freeze: Arc<Freeze>,
+}
+
+#[async_trait]
+impl Injector for FreezeInjector {
+    async fn inject(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
+        trace!("test for filter");
+        if let Some(gate) = self.freeze.gate() {
+            if self.filter.filter(ctx, method, path) {
+                debug!("park operation until thawed");
+                drop(gate.acquire().await);
+                debug!("operation resumed");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn trigger(&self) {
+        self.freeze.toggle();
+    }
+
+    fn disable(&self) {
+        self.freeze.disable();
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl FreezeInjector {
+    pub fn build(conf: FreezeConfig) -> anyhow::Result<Self> {
+        trace!("build freeze injector");
+
+        let freeze = Arc::new(Freeze {
+            gate: Mutex::new(None),
+            timeout: conf.timeout,
+            refreeze: conf.refreeze,
+            disabled: AtomicBool::new(false),
+        });
+        freeze.freeze();
+
+        if let Some(thaw_file) = conf.thaw_file {
+            let freeze = freeze.clone();
+            spawn(async move { wait_thaw_file(thaw_file, freeze).await });
+        }
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            freeze,
+        })
+    }
+}
+
+// wait_thaw_file thaws the operations whenever the file is created
+async fn wait_thaw_file(thaw_file: PathBuf, freeze: Arc<Freeze>) {
+    let mut existed = false;
+    loop {
+        let exists = tokio::fs::metadata(&thaw_file).await.is_ok();
+        if exists && !existed {
+            debug!("thaw file {} is created", thaw_file.display());
+            freeze.thaw();
+        }
+        existed = exists;
+        delay_for(THAW_FILE_INTERVAL).await;
+    }
+}
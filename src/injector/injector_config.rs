@@ -1,5 +1,6 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
+use std::path::PathBuf;
 use std::time::Duration;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -9,6 +10,24 @@ pub enum InjectorConfig {
     Latency(LatencyConfig),
     Fault(FaultsConfig),
     AttrOverride(AttrOverrideConfig),
+    Corrupt(CorruptConfig),
+    WriteFault(WriteFaultConfig),
+    ShortIo(ShortIoConfig),
+    Bandwidth(BandwidthConfig),
+    Concurrency(ConcurrencyConfig),
+    Freeze(FreezeConfig),
+    Quota(QuotaConfig),
+    BadBlock(BadBlockConfig),
+    PowerLoss(PowerLossConfig),
+    Poison(PoisonConfig),
+    Readdir(ReaddirConfig),
+    Readlink(ReadlinkConfig),
+    Xattr(XattrConfig),
+    Rename(RenameConfig),
+    Remount(RemountConfig),
+    Consistency(ConsistencyConfig),
+    Content(ContentConfig),
+    Phantom(PhantomConfig),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -16,8 +35,412 @@ pub enum InjectorConfig {
 pub struct LatencyConfig {
     #[serde(flatten)]
     pub filter: FilterConfig,
+    pub latency: Latency,
+    // an extra latency picked uniformly from [0, jitter)
+    #[serde(default, with = "humantime_serde")]
+    pub jitter: Option<Duration>,
+    #[serde(default)]
+    pub phase: Phase,
+}
+
+// Phase decides whether the injection happens before or after the real
+// operation. With the post phase, the operation has been applied even if the
+// caller receives an error.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Phase {
+    Pre,
+    Post,
+}
+
+impl Default for Phase {
+    fn default() -> Self {
+        Phase::Pre
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Latency {
+    Fixed(#[serde(with = "humantime_serde")] Duration),
+    Distribution(LatencyDistribution),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "distribution")]
+#[serde(rename_all = "camelCase")]
+pub enum LatencyDistribution {
+    Uniform {
+        #[serde(with = "humantime_serde")]
+        min: Duration,
+        #[serde(with = "humantime_serde")]
+        max: Duration,
+    },
+    Normal {
+        #[serde(with = "humantime_serde")]
+        mean: Duration,
+        #[serde(with = "humantime_serde")]
+        stddev: Duration,
+    },
+    Exponential {
+        #[serde(with = "humantime_serde")]
+        mean: Duration,
+    },
+    Pareto {
+        #[serde(with = "humantime_serde")]
+        scale: Duration,
+        shape: f64,
+        #[serde(default, with = "humantime_serde")]
+        max: Option<Duration>,
+    },
+    // file is a json array of buckets like `{"latency": "10ms", "weight": 99}`
+    Empirical {
+        file: PathBuf,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyBucket {
     #[serde(with = "humantime_serde")]
     pub latency: Duration,
+    pub weight: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BandwidthConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    // limits in bytes per second
+    pub read_bps: Option<u64>,
+    pub write_bps: Option<u64>,
+    // bytes allowed to pass without waiting, defaults to one second of data
+    pub burst: Option<u64>,
+    // use a bucket for every file instead of sharing one bucket
+    #[serde(default)]
+    pub per_file: bool,
+    // every glob has its own bucket, which is shared by the files matching
+    // it. The other files use the shared bucket, or their own ones.
+    #[serde(default)]
+    pub groups: Vec<String>,
+
+    #[serde(default, with = "humantime_serde")]
+    pub latency: Option<Duration>,
+    #[serde(default, with = "humantime_serde")]
+    pub byte_latency: Option<Duration>,
+    #[serde(default, with = "humantime_serde")]
+    pub entry_latency: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConcurrencyConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    // the count of operations served at the same time
+    pub depth: usize,
+    // the count of operations allowed to wait for a free slot. Operations
+    // beyond it fail with `errno`. Unlimited if not set.
+    pub queue: Option<usize>,
+    pub errno: Option<i32>,
+
+    // a slot is held until the operation completes, and the service time is
+    // spent in it before the operation. It's calculated from `iops` if not
+    // set. The queued and rejected counts are logged periodically.
+    #[serde(default, with = "humantime_serde")]
+    pub service_time: Option<Duration>,
+    pub iops: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FreezeConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    // thaw after the timeout since the operations are frozen
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    // thaw once this file is created
+    pub thaw_file: Option<PathBuf>,
+    // freeze again after the duration since thawed. A trigger (SIGUSR1)
+    // thaws the frozen operations, or freezes again if they're thawed.
+    #[serde(default, with = "humantime_serde")]
+    pub refreeze: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    // budgets of new bytes and inodes
+    pub bytes: Option<u64>,
+    pub inodes: Option<u64>,
+    // only charge the files created by or owned by this uid, and fail with EDQUOT
+    // instead of ENOSPC
+    pub uid: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BadBlockConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    // bad ranges of every matching file, which fail the reads matching the
+    // path and the methods
+    #[serde(default)]
+    pub ranges: Vec<ByteRange>,
+    // the probability of a block to be bad, decided when it's read for the
+    // first time by a request passing the whole filter, e.g. the percent
+    pub density: Option<f64>,
+    pub block_size: Option<u64>,
+    // clear the bad blocks touched by a write
+    #[serde(default)]
+    pub remap: bool,
+    pub errno: Option<i32>,
+}
+
+// the unsynced changes matching the filter are discarded on a power loss
+// trigger (SIGUSR2). The percent is the chance that a change is lost, so
+// with a percent below 100 a random subset of them survives.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PowerLossConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    // the changes reach the disk in any order, so the surviving changes are
+    // any subset of them. Otherwise they reach the disk in order, and the
+    // changes after the first lost one are lost as well
+    #[serde(default)]
+    pub reorder: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PoisonConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    // the errno returned by a poisoned handle, ESTALE by default
+    pub errno: Option<i32>,
+    // poison the handles whose path has been replaced by another inode,
+    // instead of the randomly chosen ones
+    #[serde(default)]
+    pub stale: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReaddirConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    // the probability of an entry to be hidden or repeated
+    pub hide: Option<f64>,
+    pub duplicate: Option<f64>,
+    // names of non-existent entries added to the listing
+    #[serde(default)]
+    pub phantoms: Vec<String>,
+    #[serde(default)]
+    pub shuffle: bool,
+    // fail the readdir request which reaches this offset, with EIO by
+    // default
+    pub fail_at: Option<usize>,
+    pub errno: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadlinkConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    pub mode: ReadlinkMode,
+    // replace the pattern in the target with the replacement
+    pub pattern: Option<String>,
+    pub replacement: Option<String>,
+    // the fixed target
+    pub target: Option<PathBuf>,
+    // the links pointing to each other in a loop. A matched link out of them
+    // points to itself.
+    #[serde(default)]
+    pub links: Vec<PathBuf>,
+    // the errno of the error mode, ELOOP by default
+    pub errno: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ReadlinkMode {
+    Replace,
+    Fixed,
+    Loop,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct XattrConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    pub mode: XattrMode,
+    // the attributes affected by the noData, hide, range and override modes.
+    // All attributes are affected if it's empty.
+    #[serde(default)]
+    pub names: Vec<String>,
+    // the value of the override mode
+    pub value: Option<Content>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum XattrMode {
+    // fail every xattr operation with ENOTSUP
+    Unsupported,
+    // fail getxattr and removexattr with ENODATA
+    NoData,
+    // like noData, and remove the names from listxattr
+    Hide,
+    // fail the data fetch after a matching size probe with ERANGE, as if the
+    // value grows in between. The retry after it succeeds.
+    Range,
+    Override,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    pub mode: RenameMode,
+    // the duration of the intermediate state of a non-atomic rename, 100ms
+    // by default
+    #[serde(default, with = "humantime_serde")]
+    pub window: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum RenameMode {
+    // fail with EXDEV as if the paths are on different filesystems
+    CrossDevice,
+    // the destination disappears before the source is renamed
+    RemoveFirst,
+    // the source stays visible after the destination appears
+    LinkFirst,
+}
+
+// after triggered, every mutating method fails with EROFS until recovery.
+// Opening a file for writing is also mutating. The filter only selects the
+// operations counted by the trigger. A trigger (SIGUSR1) recovers the
+// read-only filesystem.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RemountConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    pub trigger: RemountTrigger,
+    // the count of matching mutating operations before the trigger
+    pub count: Option<u64>,
+    // the time since mounted before the trigger
+    #[serde(default, with = "humantime_serde")]
+    pub after: Option<Duration>,
+    // the errno of the triggering fault, EIO by default
+    pub errno: Option<i32>,
+    // recover after the filesystem has been read-only for the duration
+    #[serde(default, with = "humantime_serde")]
+    pub recover: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum RemountTrigger {
+    // the first matching mutating operation fails, and the trigger follows.
+    // A fault injected by another injector also triggers it.
+    Fault,
+    Count,
+    Time,
+    // SIGUSR1, and the next one recovers
+    Signal,
+}
+
+// the writes and creations matching the filter are not visible until the
+// window passes
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsistencyConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    pub content: Content,
+    // how long the decision for a path and a process is kept, 1s by default
+    #[serde(default, with = "humantime_serde")]
+    pub window: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "source")]
+#[serde(rename_all = "camelCase")]
+pub enum Content {
+    Inline { data: String },
+    // arbitrary bytes in hex, e.g. "00ff"
+    Hex { hex: String },
+    // the content of a file on the host, read when toda starts
+    File { file: PathBuf },
+    // the byte repeated for size times
+    Repeat { byte: u8, size: usize },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PhantomConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    // synthetic files added to the namespace
+    #[serde(default)]
+    pub files: Vec<PhantomFile>,
+    // hide the existing files matching the filter
+    #[serde(default)]
+    pub hide: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PhantomFile {
+    pub path: PathBuf,
+    // only the regular files are supported, and the other kinds are rejected
+    pub kind: Option<FileType>,
+    // empty by default
+    pub content: Option<Content>,
+    pub perm: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ByteRange {
+    pub offset: u64,
+    pub length: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -27,14 +450,66 @@ pub struct FaultsConfig {
     pub filter: FilterConfig,
 
     pub faults: Vec<FaultConfig>,
+    #[serde(default)]
+    pub phase: Phase,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct FilterConfig {
     pub path: Option<String>,
     pub methods: Option<Vec<String>>,
     pub percent: i32,
+
+    // the requests are selected by the process which issues them
+    pub pids: Option<Vec<u32>>,
+    pub uids: Option<Vec<u32>>,
+    pub gids: Option<Vec<u32>>,
+
+    // the process is identified through procfs: its name, a glob of its
+    // executable path, a prefix of its cgroup path and its pid namespace
+    pub comms: Option<Vec<String>>,
+    pub exe: Option<String>,
+    pub cgroup: Option<String>,
+    pub pid_ns: Option<u64>,
+
+    // the matched requests are counted, and only some of them are injected:
+    // the first `skip` ones are passed, then every `every`th one is injected
+    // until `limit` of them have been injected
+    pub skip: Option<u64>,
+    pub every: Option<u64>,
+    pub limit: Option<u64>,
+    // once is a shorthand of limit 1
+    #[serde(default)]
+    pub once: bool,
+    #[serde(default)]
+    pub count_by: CountBy,
+
+    // READ and WRITE are selected by their range in [min, max) form. A
+    // request without a range doesn't match any of them.
+    pub min_offset: Option<u64>,
+    pub max_offset: Option<u64>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    // the operations on a file handle are selected by the flags which it's
+    // opened with, like "O_WRONLY" or "O_SYNC". All of them should be set.
+    pub flags: Option<Vec<String>>,
+}
+
+// CountBy decides whether the requests are counted together, or separately
+// for each path or each file handle
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum CountBy {
+    All,
+    Path,
+    Handle,
+}
+
+impl Default for CountBy {
+    fn default() -> Self {
+        CountBy::All
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -44,6 +519,70 @@ pub struct FaultConfig {
     pub weight: i32,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CorruptConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    pub mode: CorruptMode,
+    // number of bits to flip in `bitFlip` mode
+    pub count: Option<usize>,
+    // length of the corrupted range in `zero` and `random` mode, or the
+    // number of bytes to cut in `truncate` mode
+    pub length: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum CorruptMode {
+    BitFlip,
+    Zero,
+    Random,
+    Truncate,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteFaultConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    pub mode: WriteFaultMode,
+    // persist a random subset of sectors of this size in `torn` mode,
+    // instead of a random prefix
+    pub sector: Option<u64>,
+    // offset shift of the data in `misdirected` mode
+    pub shift: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum WriteFaultMode {
+    Lost,
+    Torn,
+    Misdirected,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ShortIoConfig {
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+
+    pub mode: ShortIoMode,
+    // the maximum length in `fixed` mode, or the boundary in `aligned` mode
+    pub size: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ShortIoMode {
+    Random,
+    Fixed,
+    Aligned,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct AttrOverrideConfig {
@@ -62,6 +601,45 @@ pub struct AttrOverrideConfig {
     pub uid: Option<u32>,
     pub gid: Option<u32>,
     pub rdev: Option<u32>,
+
+    // the relative modifiers are applied after the replacements
+    pub atime_skew: Option<Skew>,
+    pub mtime_skew: Option<Skew>,
+    pub ctime_skew: Option<Skew>,
+    pub size_delta: Option<i64>,
+    // the bits are cleared from perm, like umask
+    pub perm_mask: Option<u16>,
+}
+
+// Skew is a duration which can be negative, like "-1h 30m"
+#[derive(Clone, Copy, Debug)]
+pub struct Skew {
+    pub negative: bool,
+    pub duration: Duration,
+}
+
+impl Serialize for Skew {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let sign = if self.negative { "-" } else { "" };
+        serializer.serialize_str(&format!(
+            "{}{}",
+            sign,
+            humantime::format_duration(self.duration)
+        ))
+    }
+}
+
+impl<'de> Deserialize<'de> for Skew {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let skew = String::deserialize(deserializer)?;
+        let (negative, duration) = match skew.trim().strip_prefix('-') {
+            Some(duration) => (true, duration),
+            None => (false, skew.trim()),
+        };
+        let duration = humantime::parse_duration(duration).map_err(de::Error::custom)?;
+
+        Ok(Skew { negative, duration })
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
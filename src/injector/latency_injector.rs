@@ -1,43 +1,201 @@
 use async_trait::async_trait;
 
+use std::f64::consts::PI;
 use std::path::Path;
 use std::time::Duration;
 
 use super::filter;
-use super::injector_config::LatencyConfig;
+use super::injector_config::{Latency, LatencyBucket, LatencyConfig, LatencyDistribution, Phase};
 use super::Injector;
-use crate::hookfs::Result;
+use crate::hookfs::{RequestContext, Result};
 
+use anyhow::anyhow;
 use log::{debug, trace};
+use rand::Rng;
 use tokio::time::delay_for;
 
+#[derive(Debug)]
+enum Sampler {
+    Fixed(Duration),
+    Uniform {
+        min: f64,
+        max: f64,
+    },
+    Normal {
+        mean: f64,
+        stddev: f64,
+    },
+    Exponential {
+        mean: f64,
+    },
+    Pareto {
+        scale: f64,
+        shape: f64,
+        max: f64,
+    },
+    Empirical {
+        buckets: Vec<(Duration, u64)>,
+        sum: u64,
+    },
+}
+
+impl Sampler {
+    fn build(latency: Latency) -> anyhow::Result<Sampler> {
+        let distribution = match latency {
+            Latency::Fixed(latency) => return Ok(Sampler::Fixed(latency)),
+            Latency::Distribution(distribution) => distribution,
+        };
+
+        let sampler = match distribution {
+            LatencyDistribution::Uniform { min, max } => {
+                if min > max {
+                    return Err(anyhow!("min latency is greater than max latency"));
+                }
+                Sampler::Uniform {
+                    min: min.as_secs_f64(),
+                    max: max.as_secs_f64(),
+                }
+            }
+            LatencyDistribution::Normal { mean, stddev } => Sampler::Normal {
+                mean: mean.as_secs_f64(),
+                stddev: stddev.as_secs_f64(),
+            },
+            LatencyDistribution::Exponential { mean } => Sampler::Exponential {
+                mean: mean.as_secs_f64(),
+            },
+            LatencyDistribution::Pareto { scale, shape, max } => {
+                if shape <= 0f64 {
+                    return Err(anyhow!("shape of pareto distribution should be positive"));
+                }
+                Sampler::Pareto {
+                    scale: scale.as_secs_f64(),
+                    shape,
+                    max: max.map(|max| max.as_secs_f64()).unwrap_or(MAX_LATENCY),
+                }
+            }
+            LatencyDistribution::Empirical { file } => {
+                let file = std::fs::File::open(&file)?;
+                let buckets: Vec<LatencyBucket> = serde_json::from_reader(file)?;
+                let buckets: Vec<_> = buckets
+                    .into_iter()
+                    .map(|bucket| (bucket.latency, bucket.weight))
+                    .collect();
+
+                let sum = buckets.iter().fold(0, |acc, bucket| acc + bucket.1);
+                if sum == 0 {
+                    return Err(anyhow!("histogram is empty"));
+                }
+                Sampler::Empirical { buckets, sum }
+            }
+        };
+
+        Ok(sampler)
+    }
+
+    fn sample(&self) -> Duration {
+        let mut rng = rand::thread_rng();
+
+        let secs = match self {
+            Sampler::Fixed(latency) => return *latency,
+            Sampler::Uniform { min, max } => min + (max - min) * rng.gen::<f64>(),
+            Sampler::Normal { mean, stddev } => {
+                // Box-Muller transform
+                let u1: f64 = 1f64 - rng.gen::<f64>();
+                let u2: f64 = rng.gen();
+                mean + stddev * (-2f64 * u1.ln()).sqrt() * (2f64 * PI * u2).cos()
+            }
+            Sampler::Exponential { mean } => -mean * (1f64 - rng.gen::<f64>()).ln(),
+            Sampler::Pareto { scale, shape, max } => {
+                let secs = scale / (1f64 - rng.gen::<f64>()).powf(1f64 / shape);
+                secs.min(*max)
+            }
+            Sampler::Empirical { buckets, sum } => {
+                let mut attempt = rng.gen_range(0, sum);
+                for (latency, weight) in buckets.iter() {
+                    if attempt < *weight {
+                        return *latency;
+                    }
+                    attempt -= weight;
+                }
+                unreachable!()
+            }
+        };
+
+        if secs.is_finite() && secs > 0f64 {
+            Duration::from_secs_f64(secs.min(MAX_LATENCY))
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+// MAX_LATENCY limits the samples of unbounded distributions
+const MAX_LATENCY: f64 = 3600f64;
+
 #[derive(Debug)]
 pub struct LatencyInjector {
-    latency: Duration,
+    latency: Sampler,
+    jitter: Option<Duration>,
     filter: filter::Filter,
+    phase: Phase,
 }
 
 #[async_trait]
 impl Injector for LatencyInjector {
-    async fn inject(&self, method: &filter::Method, path: &Path) -> Result<()> {
-        trace!("test for filter");
-        if self.filter.filter(method, path) {
-            debug!("inject io delay {:?}", self.latency);
-            delay_for(self.latency).await;
-            debug!("latency finished");
+    async fn inject(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
+        if self.phase == Phase::Pre {
+            self.delay(ctx, method, path).await;
+        }
+
+        Ok(())
+    }
+
+    async fn inject_post(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
+        if self.phase == Phase::Post {
+            self.delay(ctx, method, path).await;
         }
 
         Ok(())
     }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
 }
 
 impl LatencyInjector {
+    async fn delay(&self, ctx: &RequestContext, method: &filter::Method, path: &Path) {
+        trace!("test for filter");
+        if self.filter.filter(ctx, method, path) {
+            let mut latency = self.latency.sample();
+            if let Some(jitter) = self.jitter {
+                latency += jitter.mul_f64(rand::thread_rng().gen());
+            }
+
+            debug!("inject io delay {:?}", latency);
+            delay_for(latency).await;
+            debug!("latency finished");
+        }
+    }
+
     pub fn build(conf: LatencyConfig) -> anyhow::Result<Self> {
         trace!("build latency injector");
 
         Ok(Self {
-            latency: conf.latency,
+            latency: Sampler::build(conf.latency)?,
+            jitter: conf.jitter,
             filter: filter::Filter::build(conf.filter)?,
+            phase: conf.phase,
         })
     }
 }
@@ -1,26 +1,106 @@
 mod attr_override_injector;
+mod bad_block_injector;
+mod bandwidth_injector;
+mod concurrency_injector;
+mod consistency_injector;
+mod content_injector;
+mod corrupt_injector;
 mod fault_injector;
 mod filter;
+mod freeze_injector;
 mod injector_config;
 mod latency_injector;
 mod multi_injector;
+mod phantom_injector;
+mod poison_injector;
+mod power_loss_injector;
+mod process;
+mod quota_injector;
+mod readdir_injector;
+mod readlink_injector;
+mod remount_injector;
+mod rename_injector;
+mod short_io_injector;
+mod write_fault_injector;
+mod xattr_injector;
 
 pub use filter::Method;
 pub use injector_config::InjectorConfig;
 pub use multi_injector::MultiInjector;
 
-use crate::hookfs::{Reply, Result};
+use crate::hookfs::{PowerLoss, Rename, Reply, RequestContext, Result, WriteData};
 use async_trait::async_trait;
 use fuser::FileAttr;
+use nix::errno::Errno;
 
+use std::ffi::OsStr;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Guard keeps an operation admitted until it's dropped
+pub type Guard = Box<dyn Send>;
 
 #[async_trait]
 pub trait Injector: Send + Sync + std::fmt::Debug {
-    async fn inject(&self, method: &filter::Method, path: &Path) -> Result<()>;
+    async fn inject(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()>;
+
+    // admit is called after inject. The returned guard is held until the
+    // operation completes.
+    async fn admit(
+        &self,
+        _ctx: &RequestContext,
+        _method: &filter::Method,
+        _path: &Path,
+    ) -> Result<Option<Guard>> {
+        Ok(None)
+    }
+
+    // inject_io is called before writing data, and after reading data or
+    // entries. The size is the count of bytes to be written for WRITE, which
+    // may be called once for every written range, the count of bytes
+    // returned for READ, and the count of entries returned for READDIR.
+    async fn inject_io(
+        &self,
+        _ctx: &RequestContext,
+        _method: &filter::Method,
+        _path: &Path,
+        _offset: i64,
+        _size: usize,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    // inject_post is called after a modifying operation has been applied
+    async fn inject_post(
+        &self,
+        _ctx: &RequestContext,
+        _method: &filter::Method,
+        _path: &Path,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    // inject_xattr is called before an operation on a named extended
+    // attribute. For GETXATTR, the returned value overrides the real one.
+    fn inject_xattr(
+        &self,
+        _ctx: &RequestContext,
+        _method: &filter::Method,
+        _path: &Path,
+        _name: &OsStr,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
 
     fn inject_reply(
         &self,
+        _ctx: &RequestContext,
         _method: &filter::Method,
         _path: &Path,
         _reply: &mut Reply,
@@ -28,5 +108,103 @@ pub trait Injector: Send + Sync + std::fmt::Debug {
         Ok(())
     }
 
-    fn inject_attr(&self, _attr: &mut FileAttr, _path: &Path) {}
+    fn inject_attr(&self, _ctx: &RequestContext, _attr: &mut FileAttr, _path: &Path) {}
+
+    fn inject_write(&self, _ctx: &RequestContext, _write: &mut WriteData, _path: &Path) {}
+
+    fn inject_rename(
+        &self,
+        _ctx: &RequestContext,
+        _rename: &mut Rename,
+        _path: &Path,
+        _new_path: &Path,
+    ) {
+    }
+
+    // poison is called before an operation on an open file handle. Once a
+    // handle is poisoned, it fails with the returned errno until released.
+    fn poison(
+        &self,
+        _ctx: &RequestContext,
+        _method: &filter::Method,
+        _path: &Path,
+    ) -> Option<Errno> {
+        None
+    }
+
+    // stale returns the errno for the handles whose path has been replaced
+    // by another inode since they were opened
+    fn stale(
+        &self,
+        _ctx: &RequestContext,
+        _method: &filter::Method,
+        _path: &Path,
+    ) -> Option<Errno> {
+        None
+    }
+
+    // content returns the content served instead of the real one. The size
+    // in attributes should be overridden consistently.
+    fn content(
+        &self,
+        _ctx: &RequestContext,
+        _method: &filter::Method,
+        _path: &Path,
+    ) -> Option<Arc<Vec<u8>>> {
+        None
+    }
+
+    // phantom returns the attributes of a synthetic file, which is used if
+    // the path doesn't exist in the backing filesystem
+    fn phantom(
+        &self,
+        _ctx: &RequestContext,
+        _method: &filter::Method,
+        _path: &Path,
+    ) -> Option<FileAttr> {
+        None
+    }
+
+    // hidden decides whether an existing file is missing from the namespace
+    fn hidden(&self, _ctx: &RequestContext, _method: &filter::Method, _path: &Path) -> bool {
+        false
+    }
+
+    // lag returns the window in which the change is not visible. Before the
+    // window passes, the file keeps returning the old content and
+    // attributes, and a new entry is missing from readdir.
+    fn lag(
+        &self,
+        _ctx: &RequestContext,
+        _method: &filter::Method,
+        _path: &Path,
+    ) -> Option<Duration> {
+        None
+    }
+
+    // journal decides whether the change should be recorded, so that it can
+    // be discarded on the next trigger if it has not been synced
+    fn journal(&self, _ctx: &RequestContext, _method: &filter::Method, _path: &Path) -> bool {
+        false
+    }
+
+    // fault is called when an operation fails with an error injected by any
+    // injector
+    fn fault(&self, _ctx: &RequestContext, _method: &filter::Method, _path: &Path, _errno: Errno) {}
+
+    // disable is called when the injection is disabled, e.g. before
+    // recovering. The parked operations should be resumed.
+    fn disable(&self) {}
+
+    // release is called when a file or a directory handle is released
+    fn release(&self, _fh: u64) {}
+
+    // trigger is called when toda receives an external trigger (SIGUSR1)
+    fn trigger(&self) {}
+
+    // power_loss is called when toda receives a power loss trigger
+    // (SIGUSR2), and decides which of the unsynced changes are discarded
+    fn power_loss(&self) -> Option<PowerLoss> {
+        None
+    }
 }
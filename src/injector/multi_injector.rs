@@ -1,16 +1,38 @@
 use super::attr_override_injector::AttrOverrideInjector;
+use super::bad_block_injector::BadBlockInjector;
+use super::bandwidth_injector::BandwidthInjector;
+use super::concurrency_injector::ConcurrencyInjector;
+use super::consistency_injector::ConsistencyInjector;
+use super::content_injector::ContentInjector;
+use super::corrupt_injector::CorruptInjector;
 use super::fault_injector::FaultInjector;
 use super::filter;
+use super::freeze_injector::FreezeInjector;
 use super::injector_config::InjectorConfig;
 use super::latency_injector::LatencyInjector;
-use super::Injector;
-use crate::hookfs::{Reply, Result};
+use super::phantom_injector::PhantomInjector;
+use super::poison_injector::PoisonInjector;
+use super::power_loss_injector::PowerLossInjector;
+use super::quota_injector::QuotaInjector;
+use super::readdir_injector::ReaddirInjector;
+use super::readlink_injector::ReadlinkInjector;
+use super::remount_injector::RemountInjector;
+use super::rename_injector::RenameInjector;
+use super::short_io_injector::ShortIoInjector;
+use super::write_fault_injector::WriteFaultInjector;
+use super::xattr_injector::XattrInjector;
+use super::{Guard, Injector};
+use crate::hookfs::{Error, PowerLoss, Rename, Reply, RequestContext, Result, WriteData};
 
 use async_trait::async_trait;
 use fuser::FileAttr;
 use log::trace;
+use nix::errno::Errno;
 
+use std::ffi::OsStr;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct MultiInjector {
@@ -33,35 +55,288 @@ impl MultiInjector {
                 InjectorConfig::AttrOverride(attr_override) => {
                     (box AttrOverrideInjector::build(attr_override)?) as Box<dyn Injector>
                 }
+                InjectorConfig::Corrupt(corrupt) => {
+                    (box CorruptInjector::build(corrupt)?) as Box<dyn Injector>
+                }
+                InjectorConfig::WriteFault(write_fault) => {
+                    (box WriteFaultInjector::build(write_fault)?) as Box<dyn Injector>
+                }
+                InjectorConfig::ShortIo(short_io) => {
+                    (box ShortIoInjector::build(short_io)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Bandwidth(bandwidth) => {
+                    (box BandwidthInjector::build(bandwidth)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Concurrency(concurrency) => {
+                    (box ConcurrencyInjector::build(concurrency)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Freeze(freeze) => {
+                    (box FreezeInjector::build(freeze)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Quota(quota) => {
+                    (box QuotaInjector::build(quota)?) as Box<dyn Injector>
+                }
+                InjectorConfig::BadBlock(bad_block) => {
+                    (box BadBlockInjector::build(bad_block)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Poison(poison) => {
+                    (box PoisonInjector::build(poison)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Readdir(readdir) => {
+                    (box ReaddirInjector::build(readdir)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Readlink(readlink) => {
+                    (box ReadlinkInjector::build(readlink)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Xattr(xattr) => {
+                    (box XattrInjector::build(xattr)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Rename(rename) => {
+                    (box RenameInjector::build(rename)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Remount(remount) => {
+                    (box RemountInjector::build(remount)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Consistency(consistency) => {
+                    (box ConsistencyInjector::build(consistency)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Content(content) => {
+                    (box ContentInjector::build(content)?) as Box<dyn Injector>
+                }
+                InjectorConfig::Phantom(phantom) => {
+                    (box PhantomInjector::build(phantom)?) as Box<dyn Injector>
+                }
+                InjectorConfig::PowerLoss(power_loss) => {
+                    (box PowerLossInjector::build(power_loss)?) as Box<dyn Injector>
+                }
             };
             injectors.push(injector)
         }
 
         Ok(Self { injectors })
     }
+
+    // notify tells every injector about an injected fault, and passes the
+    // result through
+    fn notify<T>(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        result: Result<T>,
+    ) -> Result<T> {
+        if let Err(Error::Sys(errno)) = &result {
+            for injector in self.injectors.iter() {
+                injector.fault(ctx, method, path, *errno)
+            }
+        }
+        result
+    }
 }
 
 #[async_trait]
 impl Injector for MultiInjector {
-    async fn inject(&self, method: &filter::Method, path: &Path) -> Result<()> {
+    async fn inject(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
         for injector in self.injectors.iter() {
-            injector.inject(method, path).await?
+            let result = injector.inject(ctx, method, path).await;
+            self.notify(ctx, method, path, result)?
         }
 
         Ok(())
     }
 
-    fn inject_reply(&self, method: &filter::Method, path: &Path, reply: &mut Reply) -> Result<()> {
+    async fn admit(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<Option<Guard>> {
+        let mut guards = Vec::new();
+        for injector in self.injectors.iter() {
+            let result = injector.admit(ctx, method, path).await;
+            if let Some(guard) = self.notify(ctx, method, path, result)? {
+                guards.push(guard)
+            }
+        }
+
+        if guards.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(box guards))
+        }
+    }
+
+    async fn inject_io(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        offset: i64,
+        size: usize,
+    ) -> Result<()> {
+        for injector in self.injectors.iter() {
+            let result = injector.inject_io(ctx, method, path, offset, size).await;
+            self.notify(ctx, method, path, result)?
+        }
+
+        Ok(())
+    }
+
+    async fn inject_post(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
+        for injector in self.injectors.iter() {
+            let result = injector.inject_post(ctx, method, path).await;
+            self.notify(ctx, method, path, result)?
+        }
+
+        Ok(())
+    }
+
+    fn inject_xattr(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        name: &OsStr,
+    ) -> Result<Option<Vec<u8>>> {
+        for injector in self.injectors.iter() {
+            let result = injector.inject_xattr(ctx, method, path, name);
+            if let Some(value) = self.notify(ctx, method, path, result)? {
+                return Ok(Some(value));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn inject_reply(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        reply: &mut Reply,
+    ) -> Result<()> {
         for injector in self.injectors.iter() {
-            injector.inject_reply(method, path, reply)?
+            let result = injector.inject_reply(ctx, method, path, reply);
+            self.notify(ctx, method, path, result)?
         }
 
         Ok(())
     }
 
-    fn inject_attr(&self, attr: &mut FileAttr, path: &Path) {
+    fn inject_attr(&self, ctx: &RequestContext, attr: &mut FileAttr, path: &Path) {
+        for injector in self.injectors.iter() {
+            injector.inject_attr(ctx, attr, path)
+        }
+    }
+
+    fn inject_write(&self, ctx: &RequestContext, write: &mut WriteData, path: &Path) {
+        for injector in self.injectors.iter() {
+            injector.inject_write(ctx, write, path)
+        }
+    }
+
+    fn inject_rename(
+        &self,
+        ctx: &RequestContext,
+        rename: &mut Rename,
+        path: &Path,
+        new_path: &Path,
+    ) {
+        for injector in self.injectors.iter() {
+            injector.inject_rename(ctx, rename, path, new_path)
+        }
+    }
+
+    fn poison(&self, ctx: &RequestContext, method: &filter::Method, path: &Path) -> Option<Errno> {
+        self.injectors
+            .iter()
+            .find_map(|injector| injector.poison(ctx, method, path))
+    }
+
+    fn stale(&self, ctx: &RequestContext, method: &filter::Method, path: &Path) -> Option<Errno> {
+        self.injectors
+            .iter()
+            .find_map(|injector| injector.stale(ctx, method, path))
+    }
+
+    fn content(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Option<Arc<Vec<u8>>> {
+        self.injectors
+            .iter()
+            .find_map(|injector| injector.content(ctx, method, path))
+    }
+
+    fn phantom(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Option<FileAttr> {
+        self.injectors
+            .iter()
+            .find_map(|injector| injector.phantom(ctx, method, path))
+    }
+
+    fn hidden(&self, ctx: &RequestContext, method: &filter::Method, path: &Path) -> bool {
+        self.injectors
+            .iter()
+            .any(|injector| injector.hidden(ctx, method, path))
+    }
+
+    fn lag(&self, ctx: &RequestContext, method: &filter::Method, path: &Path) -> Option<Duration> {
+        self.injectors
+            .iter()
+            .filter_map(|injector| injector.lag(ctx, method, path))
+            .max()
+    }
+
+    fn journal(&self, ctx: &RequestContext, method: &filter::Method, path: &Path) -> bool {
+        self.injectors
+            .iter()
+            .any(|injector| injector.journal(ctx, method, path))
+    }
+
+    fn fault(&self, ctx: &RequestContext, method: &filter::Method, path: &Path, errno: Errno) {
+        for injector in self.injectors.iter() {
+            injector.fault(ctx, method, path, errno)
+        }
+    }
+
+    fn disable(&self) {
         for injector in self.injectors.iter() {
-            injector.inject_attr(attr, path)
+            injector.disable()
         }
     }
+
+    fn release(&self, fh: u64) {
+        for injector in self.injectors.iter() {
+            injector.release(fh)
+        }
+    }
+
+    fn trigger(&self) {
+        for injector in self.injectors.iter() {
+            injector.trigger()
+        }
+    }
+
+    fn power_loss(&self) -> Option<PowerLoss> {
+        self.injectors
+            .iter()
+            .find_map(|injector| injector.power_loss())
+    }
 }
@@ -0,0 +1,191 @@
+use super::attr_override_injector::convert_file_type;
+use super::content_injector::load_content;
+use super::filter;
+use super::Injector;
+
+use super::injector_config::PhantomConfig;
+use crate::hookfs::{DirEntry, Reply, RequestContext, Result};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use fuser::{FileAttr, FileType};
+use log::{debug, trace};
+use nix::unistd::{getgid, getuid};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+// the inode numbers of phantoms are in the upper half, to avoid conflicting
+// with the real files
+const PHANTOM_INO: u64 = 1 << 63;
+
+#[derive(Debug)]
+struct Phantom {
+    attr: FileAttr,
+    content: Arc<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct PhantomInjector {
+    filter: filter::Filter,
+
+    phantoms: HashMap<PathBuf, Phantom>,
+    hide: bool,
+}
+
+// PhantomInjector filters the phantoms and the hidden files by the method of
+// the actual operation, e.g. a phantom can be found by LOOKUP but missing
+// from READDIR
+#[async_trait]
+impl Injector for PhantomInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn phantom(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Option<FileAttr> {
+        let phantom = self.phantoms.get(path)?;
+        if self.filter.filter(ctx, method, path) {
+            debug!("return phantom {}", path.display());
+            return Some(phantom.attr);
+        }
+
+        None
+    }
+
+    fn hidden(&self, ctx: &RequestContext, method: &filter::Method, path: &Path) -> bool {
+        self.hide && !self.phantoms.contains_key(path) && self.filter.filter(ctx, method, path)
+    }
+
+    fn content(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Option<Arc<Vec<u8>>> {
+        let phantom = self.phantoms.get(path)?;
+        if self.filter.filter(ctx, method, path) {
+            return Some(phantom.content.clone());
+        }
+
+        None
+    }
+
+    fn inject_reply(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        reply: &mut Reply,
+    ) -> Result<()> {
+        if let Reply::DirEntries(entries) = reply {
+            if self.hide {
+                entries.entries.retain(|entry| match entry {
+                    Ok(entry) if entry.name != "." && entry.name != ".." => {
+                        let hidden = self.filter.filter(ctx, method, &path.join(&entry.name));
+                        if hidden {
+                            trace!("hide {:?}", entry);
+                        }
+                        !hidden
+                    }
+                    _ => true,
+                });
+            }
+
+            for (phantom_path, phantom) in self.phantoms.iter() {
+                let name = match phantom_path.file_name() {
+                    Some(name) if phantom_path.parent() == Some(path) => name,
+                    _ => continue,
+                };
+                let exists = entries.entries.iter().any(|entry| match entry {
+                    Ok(entry) => entry.name == name,
+                    Err(_) => false,
+                });
+                if !exists && self.filter.filter(ctx, method, phantom_path) {
+                    trace!("add phantom {}", phantom_path.display());
+                    entries.entries.push(Ok(DirEntry::new(
+                        phantom.attr.ino,
+                        phantom.attr.kind,
+                        name.to_owned(),
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl PhantomInjector {
+    pub fn build(conf: PhantomConfig) -> anyhow::Result<Self> {
+        trace!("build phantom injector");
+
+        let now = SystemTime::now();
+        let mut phantoms = HashMap::new();
+        for file in conf.files {
+            // only the regular files can be served, as the phantoms cannot be
+            // listed or followed
+            let kind = file
+                .kind
+                .map(convert_file_type)
+                .unwrap_or(FileType::RegularFile);
+            if kind != FileType::RegularFile {
+                return Err(anyhow!(
+                    "phantom {} is not a regular file",
+                    file.path.display()
+                ));
+            }
+            let content = match file.content {
+                Some(content) => load_content(content)?,
+                None => Vec::new(),
+            };
+
+            let mut hasher = DefaultHasher::new();
+            file.path.hash(&mut hasher);
+
+            let attr = FileAttr {
+                ino: hasher.finish() | PHANTOM_INO,
+                size: content.len() as u64,
+                blocks: (content.len() as u64 + 511) / 512,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                crtime: now,
+                kind,
+                perm: file.perm.unwrap_or(0o644),
+                nlink: 1,
+                uid: getuid().as_raw(),
+                gid: getgid().as_raw(),
+                rdev: 0,
+                blksize: 4096,
+                padding: 0,
+                flags: 0,
+            };
+            phantoms.insert(
+                file.path,
+                Phantom {
+                    attr,
+                    content: Arc::new(content),
+                },
+            );
+        }
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            phantoms,
+            hide: conf.hide,
+        })
+    }
+}
@@ -0,0 +1,58 @@
+use super::filter;
+use super::injector_config::PoisonConfig;
+use super::Injector;
+use crate::hookfs::{RequestContext, Result};
+
+use async_trait::async_trait;
+use log::{debug, trace};
+use nix::errno::Errno;
+
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct PoisonInjector {
+    filter: filter::Filter,
+
+    errno: Errno,
+    stale: bool,
+}
+
+#[async_trait]
+impl Injector for PoisonInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn poison(&self, ctx: &RequestContext, method: &filter::Method, path: &Path) -> Option<Errno> {
+        if !self.stale && self.filter.filter(ctx, method, path) {
+            debug!("poison handle of {}", path.display());
+            return Some(self.errno);
+        }
+
+        None
+    }
+
+    fn stale(&self, ctx: &RequestContext, method: &filter::Method, path: &Path) -> Option<Errno> {
+        if self.stale && self.filter.filter(ctx, method, path) {
+            return Some(self.errno);
+        }
+
+        None
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl PoisonInjector {
+    pub fn build(conf: PoisonConfig) -> anyhow::Result<Self> {
+        trace!("build poison injector");
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            errno: Errno::from_i32(conf.errno.unwrap_or(libc::ESTALE)),
+            stale: conf.stale,
+        })
+    }
+}
@@ -0,0 +1,57 @@
+use super::filter;
+use super::injector_config::PowerLossConfig;
+use super::Injector;
+use crate::hookfs::{PowerLoss, RequestContext, Result};
+
+use async_trait::async_trait;
+use log::trace;
+
+use std::path::Path;
+
+// PowerLossInjector only selects the changes to journal. The journal is kept
+// and rolled back by the HookFs when a power loss trigger is received.
+#[derive(Debug)]
+pub struct PowerLossInjector {
+    filter: filter::Filter,
+
+    // the percent of the filter decides which changes are lost on the
+    // trigger, instead of which changes are journaled
+    loss: PowerLoss,
+}
+
+#[async_trait]
+impl Injector for PowerLossInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn journal(&self, ctx: &RequestContext, method: &filter::Method, path: &Path) -> bool {
+        self.filter.filter(ctx, method, path)
+    }
+
+    fn power_loss(&self) -> Option<PowerLoss> {
+        Some(self.loss)
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl PowerLossInjector {
+    pub fn build(conf: PowerLossConfig) -> anyhow::Result<Self> {
+        trace!("build power loss injector");
+
+        let mut filter = conf.filter;
+        let loss = PowerLoss {
+            probability: filter.percent as f64 / 100f64,
+            reorder: conf.reorder,
+        };
+        filter.percent = 100;
+
+        Ok(Self {
+            filter: filter::Filter::build(filter)?,
+            loss,
+        })
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use procfs::process::Process;
+use tokio::task::block_in_place;
+
+use log::{debug, trace};
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// the identity of a pid is cached for a short while, as a pid can be reused
+// after the process exits
+const CACHE_TTL: Duration = Duration::from_secs(1);
+
+// a failed lookup is also cached, so an exited process is not looked up
+// again and again
+type Cache = HashMap<u32, (Instant, Option<Arc<ProcessInfo>>)>;
+
+static CACHE: Lazy<Mutex<Cache>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// ProcessInfo is the identity of the process which issues a request
+#[derive(Debug)]
+pub struct ProcessInfo {
+    pub comm: String,
+    pub exe: Option<PathBuf>,
+    pub cgroups: Vec<String>,
+    pub pid_ns: Option<u64>,
+}
+
+impl ProcessInfo {
+    // get returns the identity of the process, or None if it has exited. The
+    // pid should be the thread group, so that the comm is the name of the
+    // process instead of one of its threads. The procfs is read without
+    // holding the cache lock, so a slow read doesn't block the requests from
+    // the other processes.
+    pub fn get(pid: u32) -> Option<Arc<ProcessInfo>> {
+        let now = Instant::now();
+        if let Some((time, info)) = CACHE.lock().unwrap().get(&pid) {
+            if now.duration_since(*time) < CACHE_TTL {
+                return info.clone();
+            }
+        }
+
+        trace!("load process info of {}", pid);
+        // the filters are synchronous, so the procfs is read in place, after
+        // the other tasks of the worker are handed over to another thread
+        let info = match block_in_place(|| ProcessInfo::load(pid)) {
+            Ok(info) => Some(Arc::new(info)),
+            Err(err) => {
+                debug!("fail to load process info of {}: {:?}", pid, err);
+                None
+            }
+        };
+
+        let mut cache = CACHE.lock().unwrap();
+        cache.retain(|_, (time, _)| now.duration_since(*time) < CACHE_TTL);
+        cache.insert(pid, (now, info.clone()));
+
+        info
+    }
+
+    fn load(pid: u32) -> Result<ProcessInfo> {
+        let process = Process::new(pid as i32)?;
+
+        let exe = process.exe().ok();
+        let cgroups = process
+            .cgroups()
+            .map(|cgroups| cgroups.into_iter().map(|cgroup| cgroup.pathname).collect())
+            .unwrap_or_default();
+        let pid_ns = nix::sys::stat::stat(format!("/proc/{}/ns/pid", pid).as_str())
+            .map(|stat| stat.st_ino)
+            .ok();
+
+        Ok(ProcessInfo {
+            comm: process.stat.comm,
+            exe,
+            cgroups,
+            pid_ns,
+        })
+    }
+}
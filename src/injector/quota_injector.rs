@@ -0,0 +1,266 @@
+use super::filter;
+use super::Injector;
+
+use super::injector_config::QuotaConfig;
+use crate::hookfs::{Error, Reply, RequestContext, Result};
+
+use async_trait::async_trait;
+use fuser::{FileAttr, FileType};
+use log::{debug, trace};
+use nix::errno::Errno;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy)]
+struct Charge {
+    // the last known size of the file
+    size: u64,
+
+    // the bytes and inode charged for the file, which are given back when it
+    // is removed. The files existing before the injection are not charged
+    bytes: u64,
+    inode: bool,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    bytes: u64,
+    inodes: u64,
+
+    // the files which can be charged, learned from their attributes or from
+    // the requests creating them
+    files: HashMap<PathBuf, Charge>,
+}
+
+impl Usage {
+    // resize charges the growth of a file, or credits the charged bytes when
+    // it shrinks
+    fn resize(&mut self, path: &Path, size: u64) {
+        if let Some(file) = self.files.get_mut(path) {
+            if size > file.size {
+                self.bytes += size - file.size;
+                file.bytes += size - file.size;
+            } else {
+                let credit = std::cmp::min(file.size - size, file.bytes);
+                self.bytes -= credit;
+                file.bytes -= credit;
+            }
+            file.size = size;
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(file) = self.files.remove(path) {
+            trace!("credit {} bytes for {}", file.bytes, path.display());
+            self.bytes -= file.bytes;
+            if file.inode {
+                self.inodes -= 1;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct QuotaInjector {
+    filter: filter::Filter,
+
+    bytes: Option<u64>,
+    inodes: Option<u64>,
+    uid: Option<u32>,
+
+    usage: Mutex<Usage>,
+}
+
+#[async_trait]
+impl Injector for QuotaInjector {
+    async fn inject(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
+        let creating = filter::Method::CREATE | filter::Method::MKDIR | filter::Method::MKNOD;
+        if !creating.contains(*method) || !self.filter.filter(ctx, method, path) {
+            return Ok(());
+        }
+
+        if self.uid.map_or(false, |uid| uid != ctx.uid) {
+            return Ok(());
+        }
+
+        let usage = self.usage.lock().unwrap();
+        if let Some(inodes) = self.inodes {
+            if usage.inodes >= inodes {
+                debug!("inode budget {} is used up", inodes);
+                return Err(Error::Sys(self.errno()));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn inject_post(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
+        let mut usage = self.usage.lock().unwrap();
+        match *method {
+            // the removed file gives back its bytes and inode
+            filter::Method::UNLINK | filter::Method::RMDIR => usage.remove(path),
+            // the new file is charged to the caller once it is created
+            filter::Method::CREATE | filter::Method::MKDIR | filter::Method::MKNOD => {
+                if !self.filter.filter(ctx, method, path)
+                    || self.uid.map_or(false, |uid| uid != ctx.uid)
+                {
+                    return Ok(());
+                }
+                if usage.files.get(path).map_or(false, |file| file.inode) {
+                    return Ok(());
+                }
+
+                trace!("charge an inode for {}", path.display());
+                usage.inodes += 1;
+                // the attributes of a created file may have been read before
+                // it's charged
+                let size = usage.files.get(path).map_or(0, |file| file.size);
+                usage.files.insert(
+                    path.to_owned(),
+                    Charge {
+                        size,
+                        bytes: 0,
+                        inode: true,
+                    },
+                );
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    async fn inject_io(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        offset: i64,
+        size: usize,
+    ) -> Result<()> {
+        if *method != filter::Method::WRITE || !self.filter.filter(ctx, method, path) {
+            return Ok(());
+        }
+
+        let mut usage = self.usage.lock().unwrap();
+        if !usage.files.contains_key(path) {
+            // the files of the user are learned from their attributes
+            if self.uid.is_some() {
+                return Ok(());
+            }
+            usage.files.insert(
+                path.to_owned(),
+                Charge {
+                    size: 0,
+                    bytes: 0,
+                    inode: false,
+                },
+            );
+        }
+
+        let known_size = usage.files[path].size;
+        let end = offset as u64 + size as u64;
+        let growth = end.saturating_sub(known_size);
+        if let Some(bytes) = self.bytes {
+            if usage.bytes + growth > bytes {
+                debug!("byte budget {} is used up", bytes);
+                return Err(Error::Sys(self.errno()));
+            }
+        }
+
+        trace!("charge {} bytes for {}", growth, path.display());
+        usage.resize(path, known_size.max(end));
+
+        Ok(())
+    }
+
+    fn inject_reply(
+        &self,
+        _ctx: &RequestContext,
+        _method: &filter::Method,
+        _path: &Path,
+        reply: &mut Reply,
+    ) -> Result<()> {
+        // the budget is the capacity of the filesystem, so it's reported for
+        // every statfs
+        if let Reply::StatFs(statfs) = reply {
+            let usage = self.usage.lock().unwrap();
+            if let Some(bytes) = self.bytes {
+                let free =
+                    bytes.saturating_sub(usage.bytes) / std::cmp::max(statfs.bsize as u64, 1);
+                statfs.bfree = statfs.bfree.min(free);
+                statfs.bavail = statfs.bavail.min(free);
+            }
+            if let Some(inodes) = self.inodes {
+                statfs.ffree = statfs.ffree.min(inodes.saturating_sub(usage.inodes));
+            }
+            trace!("override statfs {:?}", statfs);
+        }
+
+        Ok(())
+    }
+
+    fn inject_attr(&self, _ctx: &RequestContext, attr: &mut FileAttr, path: &Path) {
+        let size = match attr.kind {
+            FileType::RegularFile => attr.size,
+            _ => 0,
+        };
+
+        let mut usage = self.usage.lock().unwrap();
+        if usage.files.contains_key(path) {
+            // the usage follows the size, e.g. after a truncation
+            usage.resize(path, size);
+            return;
+        }
+
+        // only the files which can be charged are remembered
+        if !self.filter.matches_path(path) || self.uid.map_or(false, |uid| uid != attr.uid) {
+            return;
+        }
+        usage.files.insert(
+            path.to_owned(),
+            Charge {
+                size,
+                bytes: 0,
+                inode: false,
+            },
+        );
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl QuotaInjector {
+    pub fn build(conf: QuotaConfig) -> anyhow::Result<Self> {
+        trace!("build quota injector");
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            bytes: conf.bytes,
+            inodes: conf.inodes,
+            uid: conf.uid,
+            usage: Mutex::new(Usage::default()),
+        })
+    }
+
+    fn errno(&self) -> Errno {
+        match self.uid {
+            Some(_) => Errno::EDQUOT,
+            None => Errno::ENOSPC,
+        }
+    }
+}
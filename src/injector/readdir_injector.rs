@@ -0,0 +1,117 @@
+use super::filter;
+use super::Injector;
+
+use super::injector_config::ReaddirConfig;
+use crate::hookfs::{DirEntries, DirEntry, Reply, RequestContext, Result};
+
+use async_trait::async_trait;
+use fuser::FileType;
+use log::{debug, trace};
+use nix::errno::Errno;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use std::ffi::OsString;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct ReaddirInjector {
+    filter: filter::Filter,
+
+    hide: f64,
+    duplicate: f64,
+    phantoms: Vec<OsString>,
+    shuffle: bool,
+    fail_at: Option<usize>,
+    errno: Errno,
+}
+
+#[async_trait]
+impl Injector for ReaddirInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn inject_reply(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        reply: &mut Reply,
+    ) -> Result<()> {
+        if let Reply::DirEntries(entries) = reply {
+            if !self.filter.filter(ctx, method, path) {
+                return Ok(());
+            }
+
+            debug!("inject directory listing mutation");
+            self.mutate(entries);
+        }
+
+        Ok(())
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl ReaddirInjector {
+    pub fn build(conf: ReaddirConfig) -> anyhow::Result<Self> {
+        trace!("build readdir injector");
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            hide: conf.hide.unwrap_or(0f64),
+            duplicate: conf.duplicate.unwrap_or(0f64),
+            phantoms: conf.phantoms.into_iter().map(OsString::from).collect(),
+            shuffle: conf.shuffle,
+            fail_at: conf.fail_at,
+            errno: Errno::from_i32(conf.errno.unwrap_or(libc::EIO)),
+        })
+    }
+
+    // mutate keeps "." and ".." at the head of the listing, and only
+    // mutates the other entries
+    fn mutate(&self, entries: &mut DirEntries) {
+        let mut rng = rand::thread_rng();
+
+        let is_dot = |entry: &std::result::Result<DirEntry, Errno>| match entry {
+            Ok(entry) => entry.name == "." || entry.name == "..",
+            Err(_) => false,
+        };
+        let (mut listing, others): (Vec<_>, Vec<_>) = entries.entries.drain(..).partition(is_dot);
+
+        for entry in others {
+            if rng.gen::<f64>() < self.hide {
+                trace!("hide {:?}", entry);
+                continue;
+            }
+            if rng.gen::<f64>() < self.duplicate {
+                trace!("duplicate {:?}", entry);
+                listing.push(entry.clone());
+            }
+            listing.push(entry);
+        }
+
+        let dots = listing.iter().take_while(|entry| is_dot(entry)).count();
+        for name in self.phantoms.iter() {
+            let index = rng.gen_range(dots, listing.len() + 1);
+            let entry = DirEntry::new(rng.gen(), FileType::RegularFile, name.clone());
+            trace!("add phantom {:?} at {}", entry, index);
+            listing.insert(index, Ok(entry));
+        }
+
+        if self.shuffle {
+            listing[dots..].shuffle(&mut rng);
+        }
+
+        if let Some(fail_at) = self.fail_at {
+            let index = std::cmp::min(fail_at, listing.len());
+            trace!("fail at {}", index);
+            listing.insert(index, Err(self.errno));
+        }
+
+        entries.entries = listing;
+    }
+}
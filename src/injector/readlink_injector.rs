@@ -0,0 +1,108 @@
+use super::filter;
+use super::Injector;
+
+use super::injector_config::{ReadlinkConfig, ReadlinkMode};
+use crate::hookfs::{Error, Reply, RequestContext, Result};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::{debug, trace};
+use nix::errno::Errno;
+
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct ReadlinkInjector {
+    filter: filter::Filter,
+
+    mode: ReadlinkMode,
+    pattern: String,
+    replacement: String,
+    target: PathBuf,
+    links: Vec<PathBuf>,
+    errno: Errno,
+}
+
+#[async_trait]
+impl Injector for ReadlinkInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn inject_reply(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        reply: &mut Reply,
+    ) -> Result<()> {
+        if let Reply::Link(link) = reply {
+            if !self.filter.filter(ctx, method, path) {
+                return Ok(());
+            }
+
+            debug!("inject readlink {:?}", self.mode);
+            let target = match self.mode {
+                ReadlinkMode::Replace => match std::str::from_utf8(&link.data) {
+                    Ok(target) => target
+                        .replace(&self.pattern, &self.replacement)
+                        .into_bytes(),
+                    Err(_) => {
+                        trace!("skip non-UTF-8 target");
+                        return Ok(());
+                    }
+                },
+                ReadlinkMode::Fixed => self.target.as_os_str().as_bytes().to_owned(),
+                ReadlinkMode::Loop => {
+                    let next = match self.links.iter().position(|link| link == path) {
+                        Some(index) => &self.links[(index + 1) % self.links.len()],
+                        None => path,
+                    };
+                    next.as_os_str().as_bytes().to_owned()
+                }
+                ReadlinkMode::Error => return Err(Error::Sys(self.errno)),
+            };
+            trace!("rewrite target to {:?}", target);
+            link.data = target;
+        }
+
+        Ok(())
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl ReadlinkInjector {
+    pub fn build(conf: ReadlinkConfig) -> anyhow::Result<Self> {
+        trace!("build readlink injector");
+
+        // an empty pattern would match between every two characters
+        let pattern = match (conf.mode, conf.pattern) {
+            (ReadlinkMode::Replace, pattern) if pattern.as_deref().map_or(true, str::is_empty) => {
+                return Err(anyhow!(
+                    "a non-empty pattern is required by the replace mode"
+                ))
+            }
+            (_, pattern) => pattern.unwrap_or_default(),
+        };
+        let target = match (conf.mode, conf.target) {
+            (ReadlinkMode::Fixed, None) => {
+                return Err(anyhow!("target is required by the fixed mode"))
+            }
+            (_, target) => target.unwrap_or_default(),
+        };
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            mode: conf.mode,
+            pattern,
+            replacement: conf.replacement.unwrap_or_default(),
+            target,
+            links: conf.links,
+            errno: Errno::from_i32(conf.errno.unwrap_or(libc::ELOOP)),
+        })
+    }
+}
@@ -0,0 +1,179 @@
+use async_trait::async_trait;
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::filter;
+use super::injector_config::{RemountConfig, RemountTrigger};
+use super::Injector;
+use crate::hookfs::{Error, RequestContext, Result};
+
+use anyhow::anyhow;
+use log::{debug, info, trace};
+use nix::errno::Errno;
+
+#[derive(Debug)]
+enum Trigger {
+    Fault(Errno),
+    Count(u64),
+    Time(Instant),
+    Signal,
+}
+
+#[derive(Debug)]
+pub struct RemountInjector {
+    filter: filter::Filter,
+
+    trigger: Trigger,
+    recover: Option<Duration>,
+
+    // the time since the filesystem is read-only
+    readonly: Mutex<Option<Instant>>,
+    count: AtomicU64,
+    // the time trigger only fires once
+    expired: AtomicBool,
+}
+
+#[async_trait]
+impl Injector for RemountInjector {
+    async fn inject(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
+        if !mutating(ctx, method) {
+            return Ok(());
+        }
+
+        if self.is_readonly() {
+            trace!("filesystem is read-only");
+            return Err(Error::Sys(Errno::EROFS));
+        }
+
+        match self.trigger {
+            Trigger::Fault(errno) => {
+                if self.filter.filter(ctx, method, path) {
+                    self.remount();
+                    debug!("return with error {}", errno);
+                    return Err(Error::Sys(errno));
+                }
+            }
+            Trigger::Count(count) => {
+                if self.filter.filter(ctx, method, path)
+                    && self.count.fetch_add(1, Ordering::SeqCst) + 1 >= count
+                {
+                    self.remount();
+                }
+            }
+            Trigger::Time(deadline) => {
+                if Instant::now() >= deadline && !self.expired.swap(true, Ordering::SeqCst) {
+                    self.remount();
+                    return Err(Error::Sys(Errno::EROFS));
+                }
+            }
+            Trigger::Signal => {}
+        }
+
+        Ok(())
+    }
+
+    // the faults injected by the other injectors also trigger the remount
+    fn fault(&self, ctx: &RequestContext, method: &filter::Method, path: &Path, errno: Errno) {
+        if let Trigger::Fault(_) = self.trigger {
+            if errno != Errno::EROFS
+                && mutating(ctx, method)
+                && !self.is_readonly()
+                && self.filter.filter(ctx, method, path)
+            {
+                debug!("remount after the fault {}", errno);
+                self.remount();
+            }
+        }
+    }
+
+    // a trigger recovers the read-only filesystem, or remounts it with the
+    // signal trigger
+    fn trigger(&self) {
+        if self.is_readonly() {
+            self.recover();
+        } else if let Trigger::Signal = self.trigger {
+            self.remount();
+        }
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl RemountInjector {
+    pub fn build(conf: RemountConfig) -> anyhow::Result<Self> {
+        trace!("build remount injector");
+
+        let trigger = match conf.trigger {
+            RemountTrigger::Fault => {
+                Trigger::Fault(Errno::from_i32(conf.errno.unwrap_or(libc::EIO)))
+            }
+            RemountTrigger::Count => Trigger::Count(
+                conf.count
+                    .ok_or(anyhow!("count is required by the count trigger"))?,
+            ),
+            RemountTrigger::Time => Trigger::Time(
+                Instant::now()
+                    + conf
+                        .after
+                        .ok_or(anyhow!("after is required by the time trigger"))?,
+            ),
+            RemountTrigger::Signal => Trigger::Signal,
+        };
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            trigger,
+            recover: conf.recover,
+            readonly: Mutex::new(None),
+            count: AtomicU64::new(0),
+            expired: AtomicBool::new(false),
+        })
+    }
+
+    fn is_readonly(&self) -> bool {
+        let since = *self.readonly.lock().unwrap();
+        match (since, self.recover) {
+            (Some(since), Some(recover)) if since.elapsed() >= recover => {
+                self.recover();
+                false
+            }
+            (since, _) => since.is_some(),
+        }
+    }
+
+    fn remount(&self) {
+        let mut readonly = self.readonly.lock().unwrap();
+        if readonly.is_none() {
+            info!("remount read-only");
+            *readonly = Some(Instant::now());
+        }
+    }
+
+    fn recover(&self) {
+        let mut readonly = self.readonly.lock().unwrap();
+        if readonly.take().is_some() {
+            info!("remount read-write");
+            self.count.store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+// mutating tells whether the operation modifies the filesystem. Opening a
+// file for writing or truncating also fails on a read-only filesystem.
+fn mutating(ctx: &RequestContext, method: &filter::Method) -> bool {
+    let writing =
+        |flags: i32| flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0;
+
+    filter::Method::MUTATING.contains(*method)
+        || (*method == filter::Method::OPEN && ctx.flags.map_or(false, writing))
+}
@@ -0,0 +1,76 @@
+use super::filter;
+use super::Injector;
+
+use super::injector_config::{RenameConfig, RenameMode};
+use crate::hookfs::{Error, Rename, RequestContext, Result};
+
+use async_trait::async_trait;
+use log::{debug, trace};
+use nix::errno::Errno;
+
+use std::path::Path;
+use std::time::Duration;
+
+const DEFAULT_WINDOW: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub struct RenameInjector {
+    filter: filter::Filter,
+
+    mode: RenameMode,
+    window: Duration,
+}
+
+#[async_trait]
+impl Injector for RenameInjector {
+    async fn inject(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
+        if let RenameMode::CrossDevice = self.mode {
+            if *method == filter::Method::RENAME && self.filter.filter(ctx, method, path) {
+                debug!("rename across devices");
+                return Err(Error::Sys(Errno::EXDEV));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn inject_rename(
+        &self,
+        ctx: &RequestContext,
+        rename: &mut Rename,
+        path: &Path,
+        _new_path: &Path,
+    ) {
+        let steps = match self.mode {
+            RenameMode::CrossDevice => return,
+            RenameMode::RemoveFirst => Rename::RemoveFirst(self.window),
+            RenameMode::LinkFirst => Rename::LinkFirst(self.window),
+        };
+
+        if self.filter.filter(ctx, &filter::Method::RENAME, path) {
+            debug!("inject non-atomic rename {:?}", steps);
+            *rename = steps;
+        }
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl RenameInjector {
+    pub fn build(conf: RenameConfig) -> anyhow::Result<Self> {
+        trace!("build rename injector");
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            mode: conf.mode,
+            window: conf.window.unwrap_or(DEFAULT_WINDOW),
+        })
+    }
+}
@@ -0,0 +1,106 @@
+use super::filter;
+use super::Injector;
+
+use super::injector_config::{ShortIoConfig, ShortIoMode};
+use crate::hookfs::{Reply, RequestContext, Result};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::{debug, trace};
+use rand::Rng;
+
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct ShortIoInjector {
+    filter: filter::Filter,
+
+    mode: ShortIoMode,
+    size: u32,
+}
+
+#[async_trait]
+impl Injector for ShortIoInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn inject_reply(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        reply: &mut Reply,
+    ) -> Result<()> {
+        match reply {
+            Reply::Data(data) if *method == filter::Method::READ => {
+                if self.filter.filter(ctx, method, path) {
+                    let len = self.shorten(ctx, data.data.len() as u32);
+                    debug!("shorten read from {} to {}", data.data.len(), len);
+                    data.data.truncate(len as usize);
+                }
+            }
+            Reply::Write(write) => {
+                if self.filter.filter(ctx, method, path) {
+                    let len = self.shorten(ctx, write.size);
+                    debug!("shorten write from {} to {}", write.size, len);
+                    write.size = len;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl ShortIoInjector {
+    pub fn build(conf: ShortIoConfig) -> anyhow::Result<Self> {
+        trace!("build short io injector");
+
+        let size = match conf.mode {
+            ShortIoMode::Random => 0,
+            _ => conf
+                .size
+                .filter(|size| *size > 0)
+                .ok_or(anyhow!("size is required in {:?} mode", conf.mode))?,
+        };
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            mode: conf.mode,
+            size,
+        })
+    }
+
+    // shorten never returns 0 for a non-empty request, so that a short read
+    // will not be seen as the end of file. In aligned mode, the request is
+    // cut at a boundary of the file offset.
+    fn shorten(&self, ctx: &RequestContext, len: u32) -> u32 {
+        if len < 2 {
+            return len;
+        }
+
+        let mut rng = rand::thread_rng();
+        match self.mode {
+            ShortIoMode::Random => rng.gen_range(1, len),
+            ShortIoMode::Fixed => std::cmp::min(self.size, len),
+            ShortIoMode::Aligned => {
+                let size = self.size as u64;
+                let offset = ctx.offset.unwrap_or(0) as u64;
+                // the length to the first boundary after the offset
+                let first = size - offset % size;
+                if first >= len as u64 {
+                    len
+                } else {
+                    let boundaries = (len as u64 - 1 - first) / size + 1;
+                    (first + rng.gen_range(0, boundaries) * size) as u32
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use super::filter;
+use super::Injector;
+
+use super::injector_config::{WriteFaultConfig, WriteFaultMode};
+use crate::hookfs::{RequestContext, Result, WriteData};
+
+use async_trait::async_trait;
+use log::{debug, trace};
+use rand::Rng;
+
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct WriteFaultInjector {
+    filter: filter::Filter,
+
+    mode: WriteFaultMode,
+    sector: Option<u64>,
+    shift: i64,
+}
+
+#[async_trait]
+impl Injector for WriteFaultInjector {
+    async fn inject(&self, _ctx: &RequestContext, _: &filter::Method, _: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn inject_write(&self, ctx: &RequestContext, write: &mut WriteData, path: &Path) {
+        if !self.filter.filter(ctx, &filter::Method::WRITE, path) {
+            return;
+        }
+
+        debug!("inject write fault {:?}", self.mode);
+        match self.mode {
+            WriteFaultMode::Lost => {
+                trace!("drop all chunks");
+                write.chunks.clear();
+            }
+            WriteFaultMode::Torn => {
+                write.chunks = write
+                    .chunks
+                    .drain(..)
+                    .flat_map(|(offset, data)| self.tear(offset, data))
+                    .collect();
+            }
+            WriteFaultMode::Misdirected => {
+                for (offset, _) in write.chunks.iter_mut() {
+                    *offset = std::cmp::max(*offset + self.shift, 0);
+                    trace!("redirect chunk to {}", offset);
+                }
+            }
+        }
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl WriteFaultInjector {
+    pub fn build(conf: WriteFaultConfig) -> anyhow::Result<Self> {
+        trace!("build write fault injector");
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            mode: conf.mode,
+            sector: conf.sector.filter(|sector| *sector > 0),
+            shift: conf.shift.unwrap_or(4096),
+        })
+    }
+
+    // tear keeps a random prefix of the data, or a random subset of the
+    // sectors if the sector size is configured
+    fn tear(&self, offset: i64, mut data: Vec<u8>) -> Vec<(i64, Vec<u8>)> {
+        let mut rng = rand::thread_rng();
+
+        let sector = match self.sector {
+            Some(sector) => sector as i64,
+            None => {
+                let len = rng.gen_range(0, data.len() + 1);
+                trace!("keep first {} bytes of chunk at {}", len, offset);
+                data.truncate(len);
+                return vec![(offset, data)];
+            }
+        };
+
+        let mut chunks = Vec::new();
+        let end = offset + data.len() as i64;
+        let mut start = offset;
+        while start < end {
+            let next = std::cmp::min((start / sector + 1) * sector, end);
+            if rng.gen() {
+                trace!("keep sector [{}, {})", start, next);
+                let range = (start - offset) as usize..(next - offset) as usize;
+                chunks.push((start, data[range].to_vec()));
+            }
+            start = next;
+        }
+
+        chunks
+    }
+}
@@ -0,0 +1,187 @@
+use super::content_injector::load_content;
+use super::filter;
+use super::Injector;
+
+use super::injector_config::{XattrConfig, XattrMode};
+use crate::hookfs::{Error, Reply, RequestContext, Result, Xattr};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::{debug, trace};
+use nix::errno::Errno;
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Race is the state of an attribute in the range mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Race {
+    // the size has been probed, and the following fetch fails
+    Probed,
+    // the fetch has failed, and the following probe is not raced again
+    Raced,
+}
+
+#[derive(Debug)]
+pub struct XattrInjector {
+    filter: filter::Filter,
+
+    mode: XattrMode,
+    names: Vec<OsString>,
+    value: Vec<u8>,
+
+    races: Mutex<HashMap<(PathBuf, OsString), Race>>,
+}
+
+#[async_trait]
+impl Injector for XattrInjector {
+    async fn inject(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+    ) -> Result<()> {
+        let xattr_methods = filter::Method::SETXATTR
+            | filter::Method::GETXATTR
+            | filter::Method::LISTXATTR
+            | filter::Method::REMOVEXATTR;
+
+        if self.mode == XattrMode::Unsupported
+            && xattr_methods.contains(*method)
+            && self.filter.filter(ctx, method, path)
+        {
+            debug!("xattr is not supported");
+            return Err(Error::Sys(Errno::from_i32(libc::ENOTSUP)));
+        }
+
+        Ok(())
+    }
+
+    fn inject_xattr(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        name: &OsStr,
+    ) -> Result<Option<Vec<u8>>> {
+        let named_methods = filter::Method::GETXATTR | filter::Method::REMOVEXATTR;
+        if !named_methods.contains(*method) || !self.matches(name) {
+            return Ok(None);
+        }
+        if self.mode == XattrMode::Range {
+            return self.race(ctx, method, path, name);
+        }
+        if !self.filter.filter(ctx, method, path) {
+            return Ok(None);
+        }
+
+        match self.mode {
+            XattrMode::NoData | XattrMode::Hide => {
+                debug!("xattr {:?} has no data", name);
+                Err(Error::Sys(Errno::ENODATA))
+            }
+            XattrMode::Override if *method == filter::Method::GETXATTR => {
+                debug!("override xattr {:?}", name);
+                Ok(Some(self.value.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn inject_reply(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        reply: &mut Reply,
+    ) -> Result<()> {
+        if let Reply::Xattr(Xattr::Data { data }) = reply {
+            match self.mode {
+                XattrMode::Hide
+                    if *method == filter::Method::LISTXATTR
+                        && self.filter.filter(ctx, method, path) =>
+                {
+                    // the list is a sequence of null terminated names
+                    let list: Vec<u8> = data
+                        .split(|byte| *byte == 0)
+                        .filter(|name| !name.is_empty() && !self.matches(OsStr::from_bytes(name)))
+                        .flat_map(|name| name.iter().copied().chain(std::iter::once(0)))
+                        .collect();
+                    trace!("hide xattrs from list {:?}", data);
+                    *data = list;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn release(&self, fh: u64) {
+        self.filter.release(fh)
+    }
+}
+
+impl XattrInjector {
+    pub fn build(conf: XattrConfig) -> anyhow::Result<Self> {
+        trace!("build xattr injector");
+
+        let value = match (conf.mode, conf.value) {
+            (XattrMode::Override, None) => {
+                return Err(anyhow!("value is required by the override mode"))
+            }
+            (_, Some(value)) => load_content(value)?,
+            (_, None) => Vec::new(),
+        };
+
+        Ok(Self {
+            filter: filter::Filter::build(conf.filter)?,
+            mode: conf.mode,
+            names: conf.names.into_iter().map(OsString::from).collect(),
+            value,
+            races: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // race simulates a value which grows between the size probe and the data
+    // fetch, so the fetch following a chosen probe fails with ERANGE once
+    fn race(
+        &self,
+        ctx: &RequestContext,
+        method: &filter::Method,
+        path: &Path,
+        name: &OsStr,
+    ) -> Result<Option<Vec<u8>>> {
+        if *method != filter::Method::GETXATTR {
+            return Ok(None);
+        }
+
+        let key = (path.to_owned(), name.to_owned());
+        let mut races = self.races.lock().unwrap();
+        let race = races.get(&key).copied();
+        if ctx.size.unwrap_or(0) == 0 {
+            if race == Some(Race::Raced) {
+                trace!("the value of {:?} has settled", name);
+                races.remove(&key);
+            } else if self.filter.filter(ctx, method, path) {
+                races.insert(key, Race::Probed);
+            }
+            return Ok(None);
+        }
+
+        if race == Some(Race::Probed) {
+            debug!("fail the data fetch of {:?} with ERANGE", name);
+            races.insert(key, Race::Raced);
+            return Err(Error::Sys(Errno::ERANGE));
+        }
+        races.remove(&key);
+        Ok(None)
+    }
+
+    fn matches(&self, name: &OsStr) -> bool {
+        self.names.is_empty() || self.names.iter().any(|item| item == name)
+    }
+}
@@ -113,7 +113,14 @@ fn resume(option: Options, mount_guard: MountInjectionGuard) -> Result<()> {
 
 static mut SIGNAL_PIPE_WRITER: RawFd = 0;
 
-const SIGNAL_MSG: [u8; 6] = *b"SIGNAL";
+// all messages have the same length, so that they can be read one by one
+const MSG_LEN: usize = 7;
+
+const SIGNAL_MSG: [u8; MSG_LEN] = *b"RECOVER";
+
+const TRIGGER_MSG: [u8; MSG_LEN] = *b"TRIGGER";
+
+const POWER_LOSS_MSG: [u8; MSG_LEN] = *b"PWRLOSS";
 
 extern "C" fn signal_handler(_: libc::c_int) {
     unsafe {
@@ -121,6 +128,18 @@ extern "C" fn signal_handler(_: libc::c_int) {
     }
 }
 
+extern "C" fn trigger_handler(_: libc::c_int) {
+    unsafe {
+        write(SIGNAL_PIPE_WRITER, &TRIGGER_MSG).unwrap();
+    }
+}
+
+extern "C" fn power_loss_handler(_: libc::c_int) {
+    unsafe {
+        write(SIGNAL_PIPE_WRITER, &POWER_LOSS_MSG).unwrap();
+    }
+}
+
 fn main() -> Result<()> {
     let (reader, writer) = pipe()?;
     unsafe {
@@ -129,6 +148,8 @@ fn main() -> Result<()> {
 
     unsafe { signal(Signal::SIGINT, SigHandler::Handler(signal_handler))? };
     unsafe { signal(Signal::SIGTERM, SigHandler::Handler(signal_handler))? };
+    unsafe { signal(Signal::SIGUSR1, SigHandler::Handler(trigger_handler))? };
+    unsafe { signal(Signal::SIGUSR2, SigHandler::Handler(power_loss_handler))? };
 
     let option = Options::from_args();
     flexi_logger::Logger::with_str(&option.verbose)
@@ -139,8 +160,19 @@ fn main() -> Result<()> {
     let mount_injector = inject(option.clone())?;
 
     info!("waiting for signal to exit");
-    let mut buf = vec![0u8; 6];
-    read(reader, buf.as_mut_slice())?;
+    let mut buf = vec![0u8; MSG_LEN];
+    loop {
+        read(reader, buf.as_mut_slice())?;
+        if buf == TRIGGER_MSG {
+            info!("trigger injectors");
+            mount_injector.trigger();
+        } else if buf == POWER_LOSS_MSG {
+            info!("simulate a power loss");
+            mount_injector.power_loss();
+        } else {
+            break;
+        }
+    }
     info!("start to recover and exit");
 
     resume(option, mount_injector)?;
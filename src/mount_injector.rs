@@ -38,6 +38,14 @@ impl MountInjectionGuard {
         self.hookfs.disable_injection();
     }
 
+    pub fn trigger(&self) {
+        self.hookfs.trigger();
+    }
+
+    pub fn power_loss(&self) {
+        self.hookfs.power_loss();
+    }
+
     // This method should be called in host namespace
     pub fn recover_mount(mut self) -> Result<()> {
         let mount_point = self.original_path.clone();
@@ -0,0 +1,358 @@
+// Copyright 2020 Chaos Mesh Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use toda::hookfs;
+use toda::injector::{InjectorConfig, MultiInjector};
+
+use std::ffi::OsStr;
+use std::fs::{
+    metadata, read_to_string, remove_file, set_permissions, write, File, OpenOptions, Permissions,
+};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+// These tests mount a hookfs with the injectors, and check the behavior
+// through the mount point
+
+static INIT: Once = Once::new();
+
+fn init<F>(name: &str, configs: F) -> (PathBuf, Arc<hookfs::HookFs>, fuser::BackgroundSession)
+where
+    F: FnOnce(&Path) -> Value,
+{
+    let test_path_backend: PathBuf = ["/tmp/test_injector_backend", name].iter().collect();
+    let test_path: PathBuf = ["/tmp/test_injector", name].iter().collect();
+
+    INIT.call_once(|| {
+        flexi_logger::Logger::with_env().start().unwrap();
+    });
+
+    std::fs::remove_dir_all(&test_path_backend).ok();
+    std::fs::remove_dir_all(&test_path).ok();
+
+    std::fs::create_dir_all(&test_path_backend).ok();
+    std::fs::create_dir_all(&test_path).ok();
+
+    let configs: Vec<InjectorConfig> = serde_json::from_value(configs(&test_path)).unwrap();
+    let hookfs = Arc::new(hookfs::HookFs::new(
+        &test_path,
+        &test_path_backend,
+        MultiInjector::build(configs).unwrap(),
+    ));
+
+    let fs = hookfs::AsyncFileSystem::from(hookfs.clone());
+
+    let args = [
+        "allow_other",
+        "nonempty",
+        "fsname=toda",
+        "default_permissions",
+    ];
+    let flags: Vec<_> = args
+        .iter()
+        .flat_map(|item| vec![OsStr::new("-o"), OsStr::new(item)])
+        .collect();
+
+    let session = fuser::spawn_mount(fs, &test_path, &flags).unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+    hookfs.enable_injection();
+    (test_path, hookfs, session)
+}
+
+#[test]
+fn freeze_parks_only_matching_files() {
+    let (test_path, hookfs, _session) = init("freeze_parks_only_matching_files", |path| {
+        json!([{
+            "type": "freeze",
+            "path": path.join("frozen"),
+            "methods": ["read"],
+            "percent": 100,
+        }])
+    });
+
+    let frozen = test_path.join("frozen");
+    let other = test_path.join("other");
+    write(&frozen, "frozen").unwrap();
+    write(&other, "other").unwrap();
+
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || sender.send(read_to_string(&frozen).unwrap()).unwrap());
+    std::thread::sleep(Duration::from_millis(500));
+
+    // the parked read doesn't block the files which are not frozen
+    assert_eq!(read_to_string(&other).unwrap(), "other");
+    assert!(receiver.try_recv().is_err());
+
+    hookfs.trigger();
+    let content = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(content, "frozen");
+}
+
+#[test]
+fn freeze_thaws_on_disable() {
+    let (test_path, hookfs, _session) = init("freeze_thaws_on_disable", |path| {
+        json!([{
+            "type": "freeze",
+            "path": path.join("frozen"),
+            "methods": ["read"],
+            "percent": 100,
+        }])
+    });
+
+    let frozen = test_path.join("frozen");
+    write(&frozen, "frozen").unwrap();
+
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || sender.send(read_to_string(&frozen).unwrap()).unwrap());
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(receiver.try_recv().is_err());
+
+    hookfs.disable_injection();
+    let content = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(content, "frozen");
+}
+
+#[test]
+fn concurrency_rejects_beyond_queue() {
+    let (test_path, _, _session) = init("concurrency_rejects_beyond_queue", |path| {
+        json!([{
+            "type": "concurrency",
+            "path": path.join("*"),
+            "methods": ["read"],
+            "percent": 100,
+            "depth": 1,
+            "queue": 0,
+            "serviceTime": "1s",
+        }])
+    });
+
+    let paths: Vec<_> = (0..2).map(|i| test_path.join(i.to_string())).collect();
+    for path in paths.iter() {
+        write(path, "content").unwrap();
+    }
+
+    // the reads on different files are served at the same time, so the one
+    // without a slot is rejected
+    let handlers: Vec<_> = paths
+        .into_iter()
+        .map(|path| std::thread::spawn(move || read_to_string(&path)))
+        .collect();
+    let results: Vec<_> = handlers
+        .into_iter()
+        .map(|handler| handler.join().unwrap())
+        .collect();
+
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    let rejected = results.iter().find_map(|result| result.as_ref().err());
+    assert_eq!(rejected.unwrap().raw_os_error(), Some(libc::EAGAIN));
+}
+
+#[test]
+fn bandwidth_charges_returned_bytes() {
+    let (test_path, _, _session) = init("bandwidth_charges_returned_bytes", |path| {
+        json!([{
+            "type": "bandwidth",
+            "path": path.join("*"),
+            "methods": ["read"],
+            "percent": 100,
+            "readBps": 1000,
+        }])
+    });
+
+    let path = test_path.join("small");
+    write(&path, vec![b'a'; 100]).unwrap();
+
+    // the kernel asks for a whole page, but only the returned bytes are
+    // charged, so the reads fit in the burst
+    let start = Instant::now();
+    for _ in 0..5 {
+        assert_eq!(read_to_string(&path).unwrap().len(), 100);
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn bandwidth_throttles_files_in_parallel() {
+    let (test_path, _, _session) = init("bandwidth_throttles_files_in_parallel", |path| {
+        json!([{
+            "type": "bandwidth",
+            "path": path.join("*"),
+            "methods": ["read"],
+            "percent": 100,
+            "readBps": 1000,
+            "perFile": true,
+        }])
+    });
+
+    let paths: Vec<_> = (0..2).map(|i| test_path.join(i.to_string())).collect();
+    for path in paths.iter() {
+        write(path, vec![b'a'; 2000]).unwrap();
+    }
+
+    // every read waits for about a second, and they don't wait for each
+    // other
+    let start = Instant::now();
+    let handlers: Vec<_> = paths
+        .into_iter()
+        .map(|path| std::thread::spawn(move || read_to_string(&path).unwrap()))
+        .collect();
+    for handler in handlers {
+        assert_eq!(handler.join().unwrap().len(), 2000);
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(900));
+    assert!(elapsed < Duration::from_millis(1800));
+}
+
+#[test]
+fn quota_refunds_removed_files() {
+    let (test_path, _, _session) = init("quota_refunds_removed_files", |path| {
+        json!([{
+            "type": "quota",
+            "path": path.join("*"),
+            "percent": 100,
+            "bytes": 1000,
+            "inodes": 2,
+        }])
+    });
+
+    let first = test_path.join("first");
+    let second = test_path.join("second");
+    let third = test_path.join("third");
+    write(&first, vec![b'a'; 800]).unwrap();
+
+    let err = write(&second, vec![b'a'; 400]).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+    let err = write(&third, "").unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+
+    // the bytes and the inode of the removed file are given back
+    remove_file(&first).unwrap();
+    write(&second, vec![b'a'; 400]).unwrap();
+    write(&third, "").unwrap();
+}
+
+#[test]
+fn quota_credits_truncated_files() {
+    let (test_path, _, _session) = init("quota_credits_truncated_files", |path| {
+        json!([{
+            "type": "quota",
+            "path": path.join("*"),
+            "percent": 100,
+            "bytes": 1000,
+        }])
+    });
+
+    let first = test_path.join("first");
+    let second = test_path.join("second");
+    write(&first, vec![b'a'; 800]).unwrap();
+    let err = write(&second, vec![b'a'; 400]).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+
+    OpenOptions::new()
+        .write(true)
+        .open(&first)
+        .unwrap()
+        .set_len(100)
+        .unwrap();
+    write(&second, vec![b'a'; 400]).unwrap();
+}
+
+#[test]
+fn power_loss_discards_unsynced_changes() {
+    let name = "power_loss_discards_unsynced_changes";
+    let (test_path, hookfs, _session) = init(name, |path| {
+        json!([{
+            "type": "powerLoss",
+            "path": path.join("*"),
+            "percent": 100,
+        }])
+    });
+    let backend_path: PathBuf = ["/tmp/test_injector_backend", name].iter().collect();
+
+    let synced = test_path.join("synced");
+    let mut file = File::create(&synced).unwrap();
+    file.write_all(b"synced").unwrap();
+    file.sync_all().unwrap();
+    File::open(&test_path).unwrap().sync_all().unwrap();
+    let mode = metadata(&synced).unwrap().permissions().mode();
+
+    file.write_all(b" and lost").unwrap();
+    set_permissions(&synced, Permissions::from_mode(0o600)).unwrap();
+    write(test_path.join("lost"), "lost").unwrap();
+
+    // the changes after the fsync are rolled back in the backend
+    hookfs.power_loss();
+    let synced = backend_path.join("synced");
+    assert_eq!(read_to_string(&synced).unwrap(), "synced");
+    assert_eq!(metadata(&synced).unwrap().permissions().mode(), mode);
+    assert!(!backend_path.join("lost").exists());
+}
+
+#[test]
+fn pid_filter_matches_threads() {
+    let (test_path, _, _session) = init("pid_filter_matches_threads", |path| {
+        json!([{
+            "type": "fault",
+            "path": path.join("*"),
+            "methods": ["read"],
+            "percent": 100,
+            "pids": [std::process::id()],
+            "faults": [{"errno": libc::EIO, "weight": 1}],
+        }])
+    });
+
+    let path = test_path.join("file");
+    write(&path, "content").unwrap();
+
+    // the read is issued by a thread of the process, whose tid is not the pid
+    let err = std::thread::spawn(move || read_to_string(&path).unwrap_err())
+        .join()
+        .unwrap();
+    assert_eq!(err.raw_os_error(), Some(libc::EIO));
+}
+
+#[test]
+fn comm_filter_matches_process_name() {
+    // the threads have their own names, and the process is named by its main
+    // thread
+    let comm = read_to_string("/proc/self/comm").unwrap();
+    let (test_path, _, _session) = init("comm_filter_matches_process_name", |path| {
+        json!([{
+            "type": "fault",
+            "path": path.join("*"),
+            "methods": ["read"],
+            "percent": 100,
+            "comms": [comm.trim()],
+            "faults": [{"errno": libc::EIO, "weight": 1}],
+        }])
+    });
+
+    let path = test_path.join("file");
+    write(&path, "content").unwrap();
+
+    let err = std::thread::Builder::new()
+        .name("reader".to_owned())
+        .spawn(move || read_to_string(&path).unwrap_err())
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(err.raw_os_error(), Some(libc::EIO));
+}